@@ -1,14 +1,27 @@
 use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
-use fedimint_core::epoch::SerdeSignature;
 use fedimint_core::module::ApiRequestErased;
+use fedimint_core::query::UnionResponses;
 use fedimint_core::task::{MaybeSend, MaybeSync};
-use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint};
+use fedimint_nostimint_common::nip05::Nip05Document;
+use fedimint_nostimint_common::{
+    Event, GcStats, NostimintOutputOutcome, NoteId, NoteRequest, NoteStatus, RelayReceipt,
+};
+use secp256k1::XOnlyPublicKey;
 
 #[apply(async_trait_maybe_send!)]
 pub trait NostimintFederationApi {
-    async fn sign_note(&self, message: String) -> FederationResult<()>;
-    // TODO: Update returned type to be signed nostr event
-    async fn wait_signed_note(&self, message: String) -> FederationResult<SerdeSignature>;
+    async fn wait_signed_note(&self, request: NoteRequest) -> FederationResult<Event>;
+    async fn note_status(&self, request: NoteRequest) -> FederationResult<Option<NoteStatus>>;
+    async fn note_receipts(&self, id: NoteId) -> FederationResult<Vec<RelayReceipt>>;
+    async fn federation_profile(&self) -> FederationResult<Option<Event>>;
+    async fn gc_stats(&self) -> FederationResult<GcStats>;
+    async fn nostr_json(&self, name: Option<String>) -> FederationResult<Nip05Document>;
+    async fn wait_output_outcome(
+        &self,
+        out_point: OutPoint,
+    ) -> FederationResult<NostimintOutputOutcome>;
+    async fn account_balance(&self, account: XOnlyPublicKey) -> FederationResult<Amount>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -16,16 +29,63 @@ impl<T: ?Sized> NostimintFederationApi for T
 where
     T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
 {
-    async fn sign_note(&self, message: String) -> FederationResult<()> {
-        self.request_current_consensus("sign_note".to_string(), ApiRequestErased::new(message))
+    async fn wait_signed_note(&self, request: NoteRequest) -> FederationResult<Event> {
+        self.request_current_consensus(
+            "wait_signed_note".to_string(),
+            ApiRequestErased::new(request),
+        )
+        .await
+    }
+
+    async fn note_status(&self, request: NoteRequest) -> FederationResult<Option<NoteStatus>> {
+        self.request_current_consensus("note_status".to_string(), ApiRequestErased::new(request))
+            .await
+    }
+
+    async fn note_receipts(&self, id: NoteId) -> FederationResult<Vec<RelayReceipt>> {
+        // Every guardian publishes to its own relays, so we merge their answers,
+        // but only wait for a threshold of them so offline guardians can't block
+        // us, the receipts of the slowest guardians may be missing
+        let total = self.all_members().len();
+        let threshold = total - (total - 1) / 3;
+        self.request_with_strategy(
+            UnionResponses::new(threshold),
+            "note_receipts".to_string(),
+            ApiRequestErased::new(id),
+        )
+        .await
+    }
+
+    async fn federation_profile(&self) -> FederationResult<Option<Event>> {
+        self.request_current_consensus("federation_profile".to_string(), ApiRequestErased::new(()))
             .await
     }
 
-    // TODO: Update returned type to be signed nostr event
-    async fn wait_signed_note(&self, message: String) -> FederationResult<SerdeSignature> {
+    async fn gc_stats(&self) -> FederationResult<GcStats> {
+        self.request_current_consensus("gc_stats".to_string(), ApiRequestErased::new(()))
+            .await
+    }
+
+    async fn nostr_json(&self, name: Option<String>) -> FederationResult<Nip05Document> {
+        self.request_current_consensus("nostr_json".to_string(), ApiRequestErased::new(name))
+            .await
+    }
+
+    async fn wait_output_outcome(
+        &self,
+        out_point: OutPoint,
+    ) -> FederationResult<NostimintOutputOutcome> {
         self.request_current_consensus(
-            "wait_signed_note".to_string(),
-            ApiRequestErased::new(message),
+            "wait_output_outcome".to_string(),
+            ApiRequestErased::new(out_point),
+        )
+        .await
+    }
+
+    async fn account_balance(&self, account: XOnlyPublicKey) -> FederationResult<Amount> {
+        self.request_current_consensus(
+            "account_balance".to_string(),
+            ApiRequestErased::new(account),
         )
         .await
     }
@@ -0,0 +1,78 @@
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, TransactionId};
+use serde::{Deserialize, Serialize};
+
+/// Namespaces DB keys for this module
+#[repr(u8)]
+#[derive(Clone, Debug)]
+pub enum DbKeyPrefix {
+    Balance = 0x01,
+    AccountEntry = 0x02,
+}
+
+// TODO: Boilerplate-code
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// What moved funds in or out of our account
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum AccountEntryKind {
+    /// Ecash paid into the account
+    Deposit,
+    /// Funds redeemed for ecash
+    Withdrawal,
+    /// Fees paid to get a note signed
+    NoteFee,
+    /// Funds sent to another account, including fees
+    Transfer,
+    /// Fees paid to register a NIP-05 name
+    NameFee,
+}
+
+/// A movement of funds in our account
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct AccountEntry {
+    pub kind: AccountEntryKind,
+    pub amount: Amount,
+    /// The transaction that moved the funds
+    pub txid: TransactionId,
+    /// Our balance after the transaction
+    pub balance: Amount,
+}
+
+/// Our account's balance as the federation last told us
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintBalanceKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintBalancePrefix;
+
+impl_db_record!(
+    key = NostimintBalanceKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::Balance,
+);
+impl_db_lookup!(
+    key = NostimintBalanceKey,
+    query_prefix = NostimintBalancePrefix
+);
+
+/// Lookup our account's history by the time of an entry and its transaction
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintAccountEntryKey(pub u64, pub TransactionId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintAccountEntryPrefix;
+
+impl_db_record!(
+    key = NostimintAccountEntryKey,
+    value = AccountEntry,
+    db_prefix = DbKeyPrefix::AccountEntry,
+);
+impl_db_lookup!(
+    key = NostimintAccountEntryKey,
+    query_prefix = NostimintAccountEntryPrefix
+);
@@ -1,56 +1,433 @@
 use std::ffi;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
 use fedimint_client::derivable_secret::DerivableSecret;
 use fedimint_client::module::init::ClientModuleInit;
 use fedimint_client::module::{ClientModule, IClientModule};
-use fedimint_client::sm::{Context, ModuleNotifier};
+use fedimint_client::sm::{Context, ModuleNotifier, OperationId};
+use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
 
 use fedimint_client::{Client, DynGlobalClientContext};
 use fedimint_core::api::{DynGlobalApi, DynModuleApi};
 use fedimint_core::config::FederationId;
-use fedimint_core::core::{Decoder, KeyPair};
-use fedimint_core::db::Database;
+use fedimint_core::core::{Decoder, IntoDynInstance, KeyPair};
+use fedimint_core::db::{Database, ModuleDatabaseTransaction};
 use fedimint_core::module::{
     ApiVersion, CommonModuleInit, ExtendsCommonModuleInit, ModuleCommon, MultiApiVersion,
     TransactionItemAmount,
 };
 
-use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint, TransactionId};
 pub use fedimint_nostimint_common as common;
 use fedimint_nostimint_common::config::NostimintClientConfig;
-use fedimint_nostimint_common::{NostimintCommonGen, NostimintModuleTypes, KIND};
+use fedimint_nostimint_common::nip05::{self, Nip05Document};
+use fedimint_nostimint_common::{
+    kinds, Event, GcStats, NostimintCommonGen, NostimintError, NostimintInput,
+    NostimintModuleTypes, NostimintOutput, NostimintOutputOutcome, NoteId, NoteRequest, NoteStatus,
+    RelayReceipt, KIND,
+};
 
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use nostr_sdk::nips::nip19::FromBech32;
+use nostr_sdk::{EventBuilder, EventId, Keys, Kind, Tag};
 use secp256k1::{Secp256k1, XOnlyPublicKey};
-use states::NostimintStateMachine;
-use threshold_crypto::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use states::{NostimintStateMachine, Transfer};
+use threshold_crypto::PublicKey;
 use tracing::info;
 
 use crate::api::NostimintFederationApi;
+use crate::db::{
+    AccountEntry, AccountEntryKind, NostimintAccountEntryKey, NostimintAccountEntryPrefix,
+    NostimintBalanceKey,
+};
 
 pub mod api;
+pub mod db;
 mod states;
 
+/// Hex secret key `print-money` prints with, only known in development
+const FM_NOSTIMINT_ISSUER_SECRET_ENV: &str = "FM_NOSTIMINT_ISSUER_SECRET";
+
 /// Exposed API calls for client apps
 #[apply(async_trait_maybe_send!)]
 pub trait NostimintClientExt {
-    /// Request the federation signs a note for us
-    async fn fed_sign_note(&self, message: &str) -> anyhow::Result<Signature>;
+    /// Pay the federation to author and sign a note for us
+    async fn fed_sign_note(
+        &self,
+        kind: u64,
+        content: &str,
+        tags: Vec<Vec<String>>,
+    ) -> anyhow::Result<Event>;
+
+    /// Move ecash from our wallet into our account, returning its new balance
+    async fn fed_deposit(&self, amount: Amount) -> anyhow::Result<Amount>;
+
+    /// Move funds from our account back into ecash in our wallet
+    async fn fed_withdraw(&self, amount: Amount) -> anyhow::Result<()>;
+
+    /// Print ecash into our wallet with the issuer key, only works in
+    /// development federations configured with that key
+    async fn print_money(&self, issuer: KeyPair, amount: Amount) -> anyhow::Result<()>;
+
+    /// Register a NIP-05 name for our account, paying the fee from our account
+    async fn register_name(&self, name: &str) -> anyhow::Result<()>;
+
+    /// Fetch the federation's NIP-05 document, only with `name` if given
+    async fn fed_nostr_json(&self, name: Option<String>) -> anyhow::Result<Nip05Document>;
+
+    /// Send funds from our account to another one, the fees are paid from our
+    /// account too. Returns the operation tracking the transfer.
+    async fn transfer(&self, to: XOnlyPublicKey, amount: Amount) -> anyhow::Result<OperationId>;
+
+    /// Zap a user, or one of their notes, paying ecash from our wallet into
+    /// their account. Returns the zap receipt the federation signed.
+    async fn zap(
+        &self,
+        to: XOnlyPublicKey,
+        amount: Amount,
+        note: Option<EventId>,
+        comment: &str,
+    ) -> anyhow::Result<Event>;
+
+    /// Stream the status of a transfer as it progresses
+    async fn subscribe_transfer(
+        &self,
+        operation_id: OperationId,
+    ) -> BoxStream<'static, TransferStatus>;
+
+    /// Fetch our account's balance from the federation, it also counts the
+    /// transfers and zaps other accounts paid us
+    async fn balance(&self) -> anyhow::Result<Amount>;
+
+    /// Deposits, withdrawals, transfers and fees of our account, oldest first
+    async fn history(&self) -> Vec<AccountEntry>;
+
+    /// Fetch where a note request is in its lifecycle, `None` if never paid for
+    async fn fed_note_status(&self, request: NoteRequest) -> anyhow::Result<Option<NoteStatus>>;
+
+    /// Fetch what the relays of a threshold of guardians answered to a signed note
+    async fn fed_note_receipts(&self, id: EventId) -> anyhow::Result<Vec<RelayReceipt>>;
+
+    /// Fetch the federation's latest signed kind-0 profile, if it has one
+    async fn fed_profile(&self) -> anyhow::Result<Option<Event>>;
+
+    /// Fetch the federation's current session and how many notes expired
+    async fn fed_gc_stats(&self) -> anyhow::Result<GcStats>;
 
     /// Return our account
     fn account(&self) -> XOnlyPublicKey;
 
     /// Return the fed's public key
     fn fed_public_key(&self) -> PublicKey;
+
+    /// Return the fed's nostr public key that authors all notes it signs
+    fn fed_nostr_public_key(&self) -> XOnlyPublicKey;
 }
 
 #[apply(async_trait_maybe_send!)]
 impl NostimintClientExt for Client {
-    async fn fed_sign_note(&self, message: &str) -> anyhow::Result<Signature> {
+    async fn fed_sign_note(
+        &self,
+        kind: u64,
+        content: &str,
+        tags: Vec<Vec<String>>,
+    ) -> anyhow::Result<Event> {
+        let (nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        let account = nostimint.key.x_only_public_key().0;
+        let request = NoteRequest {
+            kind,
+            content: content.to_string(),
+            // Our articles only replace our own, not other accounts' ones
+            tags: kinds::namespace_identifiers(kind, tags, account),
+            created_at: unix_now(),
+        };
+
+        // Pay the note and tx fee from our account, the note is queued once accepted
+        let fee = nostimint.cfg.note_fee + nostimint.cfg.tx_fee;
+        let input = ClientInput {
+            input: NostimintInput {
+                amount: fee,
+                account,
+                note: Some(request.clone().sign(&nostimint.key)),
+                name: None,
+            },
+            keys: vec![nostimint.key],
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let tx = TransactionBuilder::new().with_input(input.into_dyn(instance.id));
+        submit_transaction(self, tx, Some((AccountEntryKind::NoteFee, fee))).await?;
+        info!("note paid for and sent to be signed: {}", content);
+
+        let event = instance.api.wait_signed_note(request).await?;
+        event.verify_federation_signature(nostimint.cfg.nostr_public_key)?;
+        Ok(event)
+    }
+
+    async fn fed_deposit(&self, amount: Amount) -> anyhow::Result<Amount> {
+        let (nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+
+        // The primary module adds the ecash inputs that balance our output
+        let output = ClientOutput {
+            output: NostimintOutput {
+                amount,
+                account: nostimint.key.x_only_public_key().0,
+                zap: None,
+            },
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+        let txid = submit_transaction(self, tx, Some((AccountEntryKind::Deposit, amount))).await?;
+        info!("deposited {amount} into our account");
+
+        // The outcome holds our balance as the federation sees it
+        let NostimintOutputOutcome(balance, _) = instance
+            .api
+            .wait_output_outcome(OutPoint { txid, out_idx: 0 })
+            .await?;
+        Ok(balance)
+    }
+
+    async fn fed_withdraw(&self, amount: Amount) -> anyhow::Result<()> {
+        let (nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+
+        // The primary module adds the ecash outputs our input pays for
+        let input = ClientInput {
+            input: NostimintInput {
+                amount,
+                account: nostimint.key.x_only_public_key().0,
+                note: None,
+                name: None,
+            },
+            keys: vec![nostimint.key],
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let tx = TransactionBuilder::new().with_input(input.into_dyn(instance.id));
+        submit_transaction(self, tx, Some((AccountEntryKind::Withdrawal, amount))).await?;
+        info!("withdrew {amount} from our account");
+
+        Ok(())
+    }
+
+    async fn print_money(&self, issuer: KeyPair, amount: Amount) -> anyhow::Result<()> {
+        let (nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        let issuer_key = issuer.x_only_public_key().0;
+        match nostimint.cfg.issuer_key {
+            None => return Err(NostimintError::PrintingDisabled.into()),
+            Some(key) if key != issuer_key => {
+                return Err(anyhow::format_err!("{issuer_key} is not the issuer key"))
+            }
+            Some(_) => {}
+        }
+
+        // The issuer's input is balanced by ecash outputs the primary module adds
+        let input = ClientInput {
+            input: NostimintInput {
+                amount,
+                account: issuer_key,
+                note: None,
+                name: None,
+            },
+            keys: vec![issuer],
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let tx = TransactionBuilder::new().with_input(input.into_dyn(instance.id));
+        submit_transaction(self, tx, None).await?;
+        info!("printed {amount} into our wallet");
+
+        Ok(())
+    }
+
+    async fn register_name(&self, name: &str) -> anyhow::Result<()> {
+        let (nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        nip05::validate_name(name)?;
+
+        // Pay the name and tx fee from our account, the name is ours once accepted
+        let fee = nostimint.cfg.name_fee + nostimint.cfg.tx_fee;
+        let input = ClientInput {
+            input: NostimintInput {
+                amount: fee,
+                account: nostimint.key.x_only_public_key().0,
+                note: None,
+                name: Some(name.to_string()),
+            },
+            keys: vec![nostimint.key],
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let tx = TransactionBuilder::new().with_input(input.into_dyn(instance.id));
+        submit_transaction(self, tx, Some((AccountEntryKind::NameFee, fee))).await?;
+        info!("registered name {name}");
+
+        Ok(())
+    }
+
+    async fn fed_nostr_json(&self, name: Option<String>) -> anyhow::Result<Nip05Document> {
         let (_nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
-        instance.api.sign_note(message.to_string()).await?;
-        info!("message sent to server to be signed: {}", message);
-        let sig = instance.api.wait_signed_note(message.to_string()).await?;
-        Ok(sig.0)
+        Ok(instance.api.nostr_json(name).await?)
+    }
+
+    async fn transfer(&self, to: XOnlyPublicKey, amount: Amount) -> anyhow::Result<OperationId> {
+        let (nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+
+        // Our input pays the fees of both itself and the output
+        let operation_id = OperationId(rand::random());
+        let started_at = unix_now();
+        let debit = amount + nostimint.cfg.tx_fee + nostimint.cfg.tx_fee;
+        let input = ClientInput {
+            input: NostimintInput {
+                amount: debit,
+                account: nostimint.key.x_only_public_key().0,
+                note: None,
+                name: None,
+            },
+            keys: vec![nostimint.key],
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let output = ClientOutput {
+            output: NostimintOutput {
+                amount,
+                account: to,
+                zap: None,
+            },
+            state_machines: Arc::new(move |txid, _| {
+                vec![NostimintStateMachine::Submitted(Transfer {
+                    operation_id,
+                    txid,
+                    to,
+                    amount,
+                    debit,
+                    started_at,
+                })]
+            }),
+        };
+        let tx = TransactionBuilder::new()
+            .with_input(input.into_dyn(instance.id))
+            .with_output(output.into_dyn(instance.id));
+        let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
+        self.finalize_and_submit_transaction(operation_id, KIND.as_str(), outpoint, tx)
+            .await?;
+        info!("transfer of {amount} to {to} submitted");
+
+        Ok(operation_id)
+    }
+
+    async fn zap(
+        &self,
+        to: XOnlyPublicKey,
+        amount: Amount,
+        note: Option<EventId>,
+        comment: &str,
+    ) -> anyhow::Result<Event> {
+        let (nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+
+        // Our account key signs the zap request, so the receipt names us as the sender
+        let keys = Keys::new(nostr_sdk::secp256k1::SecretKey::from_slice(
+            &nostimint.key.secret_bytes(),
+        )?);
+        let mut tags = vec![
+            vec!["p".to_string(), to.to_string()],
+            vec!["amount".to_string(), amount.msats.to_string()],
+        ];
+        if let Some(note) = note {
+            tags.push(vec!["e".to_string(), note.to_hex()]);
+        }
+        let tags = tags
+            .into_iter()
+            .map(Tag::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        let zap_request = Event {
+            event: EventBuilder::new(Kind::from(kinds::ZAP_REQUEST), comment, &tags)
+                .to_event(&keys)?,
+        };
+        let receipt = kinds::zap_receipt(&zap_request, to, amount)?;
+
+        // The primary module adds the ecash inputs that pay for the zap
+        let output = ClientOutput {
+            output: NostimintOutput {
+                amount,
+                account: to,
+                zap: Some(zap_request),
+            },
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+        submit_transaction(self, tx, None).await?;
+        info!("zapped {amount} to {to}");
+
+        let event = instance.api.wait_signed_note(receipt).await?;
+        event.verify_federation_signature(nostimint.cfg.nostr_public_key)?;
+        Ok(event)
+    }
+
+    async fn subscribe_transfer(
+        &self,
+        operation_id: OperationId,
+    ) -> BoxStream<'static, TransferStatus> {
+        let (nostimint, _instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        nostimint
+            .notifier
+            .subscribe(operation_id)
+            .await
+            .map(|state| match state {
+                NostimintStateMachine::Submitted(_) => TransferStatus::Submitted,
+                NostimintStateMachine::Accepted(_) => TransferStatus::Accepted,
+                NostimintStateMachine::Credited(..) => TransferStatus::Credited,
+                NostimintStateMachine::Rejected(_, reason) => TransferStatus::Rejected(reason),
+                NostimintStateMachine::Failed(_, reason) => TransferStatus::Failed(reason),
+            })
+            .boxed()
+    }
+
+    async fn balance(&self) -> anyhow::Result<Amount> {
+        let (_nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        let balance = instance.api.account_balance(self.account()).await?;
+        let mut dbtx = instance.db.begin_transaction().await;
+        dbtx.with_module_prefix(instance.id)
+            .insert_entry(&NostimintBalanceKey, &balance)
+            .await;
+        dbtx.commit_tx().await;
+        Ok(balance)
+    }
+
+    async fn history(&self) -> Vec<AccountEntry> {
+        let (_nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        let mut dbtx = instance.db.begin_transaction().await;
+        let history = dbtx
+            .with_module_prefix(instance.id)
+            .find_by_prefix(&NostimintAccountEntryPrefix)
+            .await
+            .map(|(_, entry)| entry)
+            .collect()
+            .await;
+        dbtx.commit_tx().await;
+        history
+    }
+
+    async fn fed_note_status(&self, request: NoteRequest) -> anyhow::Result<Option<NoteStatus>> {
+        let (_nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        Ok(instance.api.note_status(request).await?)
+    }
+
+    async fn fed_note_receipts(&self, id: EventId) -> anyhow::Result<Vec<RelayReceipt>> {
+        let (_nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        Ok(instance.api.note_receipts(NoteId(id)).await?)
+    }
+
+    async fn fed_profile(&self) -> anyhow::Result<Option<Event>> {
+        let (nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        let profile = instance.api.federation_profile().await?;
+        if let Some(profile) = &profile {
+            profile.verify_federation_signature(nostimint.cfg.nostr_public_key)?;
+        }
+        Ok(profile)
+    }
+
+    async fn fed_gc_stats(&self) -> anyhow::Result<GcStats> {
+        let (_nostimint, instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        Ok(instance.api.gc_stats().await?)
     }
 
     fn account(&self) -> XOnlyPublicKey {
@@ -62,6 +439,26 @@ impl NostimintClientExt for Client {
         let (nostimint, _instance) = self.get_first_module::<NostimintClientModule>(&KIND);
         nostimint.cfg.fed_public_key
     }
+
+    fn fed_nostr_public_key(&self) -> XOnlyPublicKey {
+        let (nostimint, _instance) = self.get_first_module::<NostimintClientModule>(&KIND);
+        nostimint.cfg.nostr_public_key
+    }
+}
+
+/// Where a transfer from our account is
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TransferStatus {
+    /// Waiting for the federation to accept the transaction
+    Submitted,
+    /// Our account was debited
+    Accepted,
+    /// The recipient's account was credited
+    Credited,
+    /// The federation rejected the transaction, so no funds moved
+    Rejected(String),
+    /// The transaction was accepted, but we couldn't learn its outcome
+    Failed(String),
 }
 
 #[derive(Debug)]
@@ -75,6 +472,8 @@ pub struct NostimintClientModule {
 #[derive(Debug, Clone)]
 pub struct NostimintClientContext {
     pub nostimint_decoder: Decoder,
+    /// Our account, to fetch its balance once a transfer debited it
+    pub account: XOnlyPublicKey,
 }
 
 // TODO: Boiler-plate
@@ -89,13 +488,22 @@ impl ClientModule for NostimintClientModule {
     fn context(&self) -> Self::ModuleStateMachineContext {
         NostimintClientContext {
             nostimint_decoder: self.decoder(),
+            account: self.key.x_only_public_key().0,
         }
     }
 
     fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
+        let note_fee = match input.note {
+            Some(_) => self.cfg.note_fee,
+            None => Amount::ZERO,
+        };
+        let name_fee = match input.name {
+            Some(_) => self.cfg.name_fee,
+            None => Amount::ZERO,
+        };
         TransactionItemAmount {
             amount: input.amount,
-            fee: self.cfg.tx_fee,
+            fee: self.cfg.tx_fee + note_fee + name_fee,
         }
     }
 
@@ -103,9 +511,13 @@ impl ClientModule for NostimintClientModule {
         &self,
         output: &<Self::Common as ModuleCommon>::Output,
     ) -> TransactionItemAmount {
+        let note_fee = match output.zap {
+            Some(_) => self.cfg.note_fee,
+            None => Amount::ZERO,
+        };
         TransactionItemAmount {
             amount: output.amount,
-            fee: self.cfg.tx_fee,
+            fee: self.cfg.tx_fee + note_fee,
         }
     }
 
@@ -134,14 +546,166 @@ impl ClientModule for NostimintClientModule {
                     ));
                 }
 
-                // TODO: craft other note types
+                let event = client
+                    .fed_sign_note(kinds::TEXT_NOTE, &args[1].to_string_lossy(), vec![])
+                    .await?;
+
+                Ok(serde_json::to_value(event.event)?)
+            }
+            "sign-event" => {
+                if args.len() != 3 && args.len() != 4 {
+                    return Err(anyhow::format_err!(
+                        "`sign-event` command expects 2 or 3 arguments: <kind> <content> [<tags as json>]"
+                    ));
+                }
+
+                let kind = args[1].to_string_lossy().parse::<u64>()?;
+                let tags = match args.get(3) {
+                    Some(tags) => serde_json::from_str(&tags.to_string_lossy())?,
+                    None => vec![],
+                };
+                let event = client
+                    .fed_sign_note(kind, &args[2].to_string_lossy(), tags)
+                    .await?;
+
+                Ok(serde_json::to_value(event.event)?)
+            }
+            "deposit" => {
+                if args.len() != 2 {
+                    return Err(anyhow::format_err!(
+                        "`deposit` command expects 1 argument: <amount in msats>"
+                    ));
+                }
+
+                let amount = Amount::from_msats(args[1].to_string_lossy().parse::<u64>()?);
+                let balance = client.fed_deposit(amount).await?;
+
+                Ok(serde_json::to_value(balance)?)
+            }
+            "withdraw" => {
+                if args.len() != 2 {
+                    return Err(anyhow::format_err!(
+                        "`withdraw` command expects 1 argument: <amount in msats>"
+                    ));
+                }
+
+                let amount = Amount::from_msats(args[1].to_string_lossy().parse::<u64>()?);
+                client.fed_withdraw(amount).await?;
+
+                Ok(serde_json::Value::Null)
+            }
+            "print-money" => {
+                if args.len() != 2 {
+                    return Err(anyhow::format_err!(
+                        "`print-money` command expects 1 argument: <amount in msats>"
+                    ));
+                }
+
+                let amount = Amount::from_msats(args[1].to_string_lossy().parse::<u64>()?);
+                let issuer = std::env::var(FM_NOSTIMINT_ISSUER_SECRET_ENV).map_err(|_| {
+                    anyhow::format_err!("`print-money` needs the issuer's secret key in {FM_NOSTIMINT_ISSUER_SECRET_ENV}")
+                })?;
+                let issuer = KeyPair::from_seckey_str(&Secp256k1::new(), &issuer)?;
+                client.print_money(issuer, amount).await?;
+
+                Ok(serde_json::Value::Null)
+            }
+            "transfer" => {
+                if args.len() != 3 {
+                    return Err(anyhow::format_err!(
+                        "`transfer` command expects 2 arguments: <npub or hex pubkey> <amount in msats>"
+                    ));
+                }
+
+                let to = parse_public_key(&args[1].to_string_lossy())?;
+                let amount = Amount::from_msats(args[2].to_string_lossy().parse::<u64>()?);
+                let operation_id = client.transfer(to, amount).await?;
+
+                // Wait until the transfer either went through or failed
+                let mut updates = client.subscribe_transfer(operation_id).await;
+                while let Some(status) = updates.next().await {
+                    info!("transfer status: {status:?}");
+                    if matches!(
+                        status,
+                        TransferStatus::Credited
+                            | TransferStatus::Rejected(_)
+                            | TransferStatus::Failed(_)
+                    ) {
+                        return Ok(serde_json::to_value(status)?);
+                    }
+                }
+                Err(anyhow::format_err!("Transfer updates ended unexpectedly"))
+            }
+            "zap" => {
+                if !(3..=5).contains(&args.len()) {
+                    return Err(anyhow::format_err!(
+                        "`zap` command expects 2 to 4 arguments: <npub or hex pubkey> <amount in msats> [<event id>] [<comment>]"
+                    ));
+                }
+
+                let to = parse_public_key(&args[1].to_string_lossy())?;
+                let amount = Amount::from_msats(args[2].to_string_lossy().parse::<u64>()?);
+                let note = match args.get(3) {
+                    Some(id) => Some(EventId::from_hex(id.to_string_lossy())?),
+                    None => None,
+                };
+                let comment = args
+                    .get(4)
+                    .map(|comment| comment.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let receipt = client.zap(to, amount, note, &comment).await?;
+
+                Ok(serde_json::to_value(receipt.event)?)
+            }
+            "register-name" => {
+                if args.len() != 2 {
+                    return Err(anyhow::format_err!(
+                        "`register-name` command expects 1 argument: <name>"
+                    ));
+                }
+
+                client.register_name(&args[1].to_string_lossy()).await?;
+
+                Ok(serde_json::Value::Null)
+            }
+            "nostr-json" => {
+                if args.len() > 2 {
+                    return Err(anyhow::format_err!(
+                        "`nostr-json` command expects at most 1 argument: [<name>]"
+                    ));
+                }
+
+                let name = args.get(1).map(|name| name.to_string_lossy().to_string());
+                let document = client.fed_nostr_json(name).await?;
 
-                Ok(serde_json::to_value(
-                    client.fed_sign_note(&args[1].to_string_lossy()).await?,
-                )?)
+                Ok(serde_json::to_value(document)?)
+            }
+            "balance" => Ok(serde_json::to_value(client.balance().await?)?),
+            "history" => Ok(serde_json::to_value(client.history().await)?),
+            "note-receipts" => {
+                if args.len() != 2 {
+                    return Err(anyhow::format_err!(
+                        "`note-receipts` command expects 1 argument: <event id>"
+                    ));
+                }
+
+                let id = EventId::from_hex(args[1].to_string_lossy())?;
+                let receipts = client.fed_note_receipts(id).await?;
+
+                Ok(serde_json::to_value(receipts)?)
+            }
+            "profile" => {
+                let profile = client.fed_profile().await?;
+
+                Ok(serde_json::to_value(profile.map(|event| event.event))?)
+            }
+            "gc-stats" => {
+                let stats = client.fed_gc_stats().await?;
+
+                Ok(serde_json::to_value(stats)?)
             }
             command => Err(anyhow::format_err!(
-                "Unknown command: {command}, supported commands: print-money"
+                "Unknown command: {command}, supported commands: sign-note, sign-event, deposit, withdraw, print-money, transfer, zap, register-name, nostr-json, balance, history, note-receipts, profile, gc-stats"
             )),
         }
     }
@@ -183,3 +747,83 @@ impl ClientModuleInit for NostimintClientGen {
         })
     }
 }
+
+/// Submits a transaction of our module and waits until the federation accepted
+/// it. If it moved funds of our account, it is recorded in the account's
+/// history along with the balance the federation reports after it.
+async fn submit_transaction(
+    client: &Client,
+    tx: TransactionBuilder,
+    entry: Option<(AccountEntryKind, Amount)>,
+) -> anyhow::Result<TransactionId> {
+    let (_nostimint, instance) = client.get_first_module::<NostimintClientModule>(&KIND);
+    let op_id = OperationId(rand::random());
+    let started_at = unix_now();
+    let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
+    let txid = client
+        .finalize_and_submit_transaction(op_id, KIND.as_str(), outpoint, tx)
+        .await?;
+    client
+        .transaction_updates(op_id)
+        .await
+        .await_tx_accepted(txid)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    if let Some((kind, amount)) = entry {
+        let balance = instance.api.account_balance(client.account()).await?;
+        let mut dbtx = instance.db.begin_transaction().await;
+        record_entry(
+            &mut dbtx.with_module_prefix(instance.id),
+            kind,
+            amount,
+            txid,
+            balance,
+            started_at,
+        )
+        .await;
+        dbtx.commit_tx().await;
+    }
+
+    Ok(txid)
+}
+
+/// Records a transaction of our account with the balance the federation sees
+/// after it, at the time we started the operation. Other accounts can pay us
+/// without us noticing, so we never derive the balance from our own entries.
+async fn record_entry(
+    dbtx: &mut ModuleDatabaseTransaction<'_>,
+    kind: AccountEntryKind,
+    amount: Amount,
+    txid: TransactionId,
+    balance: Amount,
+    timestamp: u64,
+) {
+    let entry = AccountEntry {
+        kind,
+        amount,
+        txid,
+        balance,
+    };
+    dbtx.insert_entry(&NostimintAccountEntryKey(timestamp, txid), &entry)
+        .await;
+    dbtx.insert_entry(&NostimintBalanceKey, &balance).await;
+}
+
+/// Current unix time in seconds
+fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time is after the epoch")
+        .as_secs()
+}
+
+/// Parses an account's public key from its npub or hex encoding
+fn parse_public_key(key: &str) -> anyhow::Result<XOnlyPublicKey> {
+    let key = if key.starts_with("npub") {
+        nostr_sdk::secp256k1::XOnlyPublicKey::from_bech32(key)?
+    } else {
+        nostr_sdk::secp256k1::XOnlyPublicKey::from_str(key)?
+    };
+    Ok(XOnlyPublicKey::from_slice(&key.serialize())?)
+}
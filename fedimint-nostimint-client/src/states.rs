@@ -1,3 +1,7 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
 use fedimint_client::sm::{DynState, OperationId, State, StateTransition};
 
 use fedimint_client::DynGlobalClientContext;
@@ -5,15 +9,54 @@ use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::{IntoDynInstance, ModuleInstanceId};
 
 use fedimint_core::encoding::{Decodable, Encodable};
-
+use fedimint_core::task::sleep;
+use fedimint_core::{Amount, OutPoint, TransactionId};
+use fedimint_nostimint_common::NostimintOutputOutcome;
+use secp256k1::XOnlyPublicKey;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::warn;
+
+use crate::api::NostimintFederationApi;
+use crate::db::AccountEntryKind;
+use crate::{record_entry, NostimintClientContext};
+
+/// How long we wait before asking the federation for an outcome again
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
 
-use crate::NostimintClientContext;
+/// How often we ask the federation before we give up on an outcome
+const MAX_ATTEMPTS: u32 = 30;
 
-/// Tracks a transaction
+/// A transfer from our account to another one
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
-pub enum NostimintStateMachine {}
+pub struct Transfer {
+    pub operation_id: OperationId,
+    pub txid: TransactionId,
+    /// Account that receives the funds
+    pub to: XOnlyPublicKey,
+    /// Amount the recipient receives
+    pub amount: Amount,
+    /// Amount taken from our account, including fees
+    pub debit: Amount,
+    /// When we started the transfer, the time of its entry in our history
+    pub started_at: u64,
+}
+
+/// Tracks a transfer from our account
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum NostimintStateMachine {
+    /// Waiting for the federation to accept the transaction
+    Submitted(Transfer),
+    /// Our account was debited, waiting for the recipient to be credited
+    Accepted(Transfer),
+    /// The recipient was credited, the outcome holds their new balance
+    Credited(Transfer, NostimintOutputOutcome),
+    /// The federation rejected the transaction, so no funds moved
+    Rejected(Transfer, String),
+    /// The transaction was accepted, but the federation didn't tell us its
+    /// outcome, so it is missing from our history
+    Failed(Transfer, String),
+}
 
 impl State for NostimintStateMachine {
     type ModuleContext = NostimintClientContext;
@@ -21,14 +64,112 @@ impl State for NostimintStateMachine {
 
     fn transitions(
         &self,
-        _context: &Self::ModuleContext,
-        _global_context: &Self::GlobalContext,
+        context: &Self::ModuleContext,
+        global_context: &Self::GlobalContext,
     ) -> Vec<StateTransition<Self>> {
-        todo!()
+        match self.clone() {
+            NostimintStateMachine::Submitted(transfer) => vec![StateTransition::new(
+                await_tx_accepted(global_context.clone(), transfer.operation_id, transfer.txid),
+                move |_dbtx, result, _state| {
+                    let transfer = transfer.clone();
+                    Box::pin(async move {
+                        match result {
+                            Ok(()) => NostimintStateMachine::Accepted(transfer),
+                            Err(e) => NostimintStateMachine::Rejected(transfer, e),
+                        }
+                    })
+                },
+            )],
+            NostimintStateMachine::Accepted(transfer) => vec![StateTransition::new(
+                await_outcome(global_context.clone(), transfer.txid, context.account),
+                move |dbtx, result, _state| {
+                    let transfer = transfer.clone();
+                    Box::pin(async move {
+                        match result {
+                            Ok((balance, outcome)) => {
+                                record_entry(
+                                    dbtx.module_tx(),
+                                    AccountEntryKind::Transfer,
+                                    transfer.debit,
+                                    transfer.txid,
+                                    balance,
+                                    transfer.started_at,
+                                )
+                                .await;
+                                NostimintStateMachine::Credited(transfer, outcome)
+                            }
+                            Err(e) => NostimintStateMachine::Failed(transfer, e),
+                        }
+                    })
+                },
+            )],
+            NostimintStateMachine::Credited(..)
+            | NostimintStateMachine::Rejected(..)
+            | NostimintStateMachine::Failed(..) => vec![],
+        }
     }
 
     fn operation_id(&self) -> OperationId {
-        todo!()
+        match self {
+            NostimintStateMachine::Submitted(transfer)
+            | NostimintStateMachine::Accepted(transfer)
+            | NostimintStateMachine::Credited(transfer, _)
+            | NostimintStateMachine::Rejected(transfer, _)
+            | NostimintStateMachine::Failed(transfer, _) => transfer.operation_id,
+        }
+    }
+}
+
+/// Waits for the federation to accept or reject our transfer
+async fn await_tx_accepted(
+    global_context: DynGlobalClientContext,
+    operation_id: OperationId,
+    txid: TransactionId,
+) -> Result<(), String> {
+    global_context
+        .await_tx_accepted(operation_id, txid)
+        .await
+        .map_err(|e| format!("Transaction was rejected: {e:?}"))
+}
+
+/// Waits for the outcome of our accepted transfer, returning the balance the
+/// federation sees for our account after it was debited and the outcome of
+/// the recipient's output
+async fn await_outcome(
+    global_context: DynGlobalClientContext,
+    txid: TransactionId,
+    account: XOnlyPublicKey,
+) -> Result<(Amount, NostimintOutputOutcome), String> {
+    let api = global_context.module_api();
+    let balance = retry("our balance", txid, || api.account_balance(account)).await?;
+    let out_point = OutPoint { txid, out_idx: 0 };
+    let outcome = retry("the transfer outcome", txid, || {
+        api.wait_output_outcome(out_point)
+    })
+    .await?;
+    Ok((balance, outcome))
+}
+
+/// Asks the federation until it answers, giving up after [`MAX_ATTEMPTS`]
+async fn retry<T, E, F, Fut>(what: &str, txid: TransactionId, request: F) -> Result<T, String>
+where
+    E: Display,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match request().await {
+            Ok(answer) => return Ok(answer),
+            Err(e) if attempts >= MAX_ATTEMPTS => {
+                return Err(format!("Failed to fetch {what} {attempts} times: {e}"))
+            }
+            Err(e) => {
+                warn!(%txid, attempts, "Failed to fetch {what}: {e}");
+                sleep(RETRY_INTERVAL).await;
+            }
+        }
     }
 }
 
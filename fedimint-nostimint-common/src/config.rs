@@ -1,10 +1,15 @@
+use std::collections::BTreeSet;
+
 use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{plugin_types_trait_impl_config, Amount};
+use secp256k1::{SecretKey, XOnlyPublicKey};
 use serde::{Deserialize, Serialize};
 use threshold_crypto::serde_impl::SerdeSecret;
 use threshold_crypto::{PublicKey, PublicKeySet, SecretKeyShare};
 
+use crate::frost::FrostPublicKeySet;
+use crate::kinds;
 use crate::NostimintCommonGen;
 
 /// Parameters necessary to generate this module's configuration
@@ -16,20 +21,89 @@ pub struct NostimintGenParams {
 
 /// Local parameters for config generation
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct NostimintGenParamsLocal(pub String);
+pub struct NostimintGenParamsLocal {
+    pub example: String,
+    pub relays: Vec<String>,
+    pub publish_attempts: u32,
+    pub policy: NotePolicyConfig,
+    pub relay_bind: Option<String>,
+}
+
+/// Notes a guardian refuses to sign, the defaults accept every note
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(default)]
+pub struct NotePolicyConfig {
+    /// Max length of a note's content in bytes
+    pub max_content_len: Option<u64>,
+    /// Max number of tags of a note
+    pub max_tags: Option<u64>,
+    /// Notes containing any of these words are vetoed, ignoring case
+    pub banned_words: Vec<String>,
+    /// If set, we only sign these of the federation's allowed kinds
+    pub allowed_kinds: Option<BTreeSet<u64>>,
+    /// If not empty, only notes of these requesters are signed
+    pub allowed_requesters: BTreeSet<XOnlyPublicKey>,
+    /// Requesters whose notes are never signed
+    pub denied_requesters: BTreeSet<XOnlyPublicKey>,
+}
 
 /// Consensus parameters for config generation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NostimintGenParamsConsensus {
     pub tx_fee: Amount,
+    pub note_fee: Amount,
+    pub name_fee: Amount,
+    pub timestamp_tolerance: u64,
+    pub allowed_kinds: BTreeSet<u64>,
+    pub note_quota: NoteQuota,
+    pub session_length: u64,
+    pub expiry_sessions: u64,
+    /// Key that can print money, only for development federations
+    pub issuer_key: Option<XOnlyPublicKey>,
+}
+
+/// How many notes each account may get signed within a window of time
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct NoteQuota {
+    pub max_notes: u64,
+    /// Length of the window in seconds, windows start at multiples of it
+    pub window: u64,
+}
+
+impl NoteQuota {
+    /// The window a note requested in `session` counts towards, sessions last
+    /// `session_length` seconds
+    ///
+    /// Windows follow the sessions every guardian agreed on, not the time of
+    /// the request, which the client chooses.
+    pub fn window_of(&self, session: u64, session_length: u64) -> u64 {
+        session.saturating_mul(session_length) / self.window.max(1)
+    }
 }
 
 impl Default for NostimintGenParams {
     fn default() -> Self {
         Self {
-            local: NostimintGenParamsLocal("example".to_string()),
+            local: NostimintGenParamsLocal {
+                example: "example".to_string(),
+                relays: vec![],
+                publish_attempts: default_publish_attempts(),
+                policy: NotePolicyConfig::default(),
+                relay_bind: None,
+            },
             consensus: NostimintGenParamsConsensus {
                 tx_fee: Amount::ZERO,
+                note_fee: Amount::from_sats(1),
+                name_fee: Amount::from_sats(1),
+                timestamp_tolerance: 600,
+                allowed_kinds: kinds::default_allowed_kinds(),
+                note_quota: NoteQuota {
+                    max_notes: 60,
+                    window: 3600,
+                },
+                session_length: 60,
+                expiry_sessions: 60,
+                issuer_key: None,
             },
         }
     }
@@ -48,13 +122,50 @@ pub struct NostimintConfig {
 pub struct NostimintClientConfig {
     /// Accessible to clients
     pub tx_fee: Amount,
+    /// Paid on top of the tx fee by inputs requesting a note
+    pub note_fee: Amount,
+    /// Paid on top of the tx fee by inputs registering a name
+    pub name_fee: Amount,
     pub fed_public_key: PublicKey,
+    /// The federation's npub, every note it signs is authored by this key
+    pub nostr_public_key: XOnlyPublicKey,
+    /// Key that can print money, only set for development federations
+    #[serde(default)]
+    pub issuer_key: Option<XOnlyPublicKey>,
+}
+
+impl NostimintClientConfig {
+    /// The federation's public key as used by nostr
+    pub fn nostr_public_key(&self) -> nostr_sdk::secp256k1::XOnlyPublicKey {
+        nostr_sdk::secp256k1::XOnlyPublicKey::from_slice(&self.nostr_public_key.serialize())
+            .expect("same curve")
+    }
 }
 
 /// Locally unencrypted config unique to each member
+///
+/// Fields added after the first release have defaults, so configs written
+/// before them still load.
 #[derive(Clone, Debug, Serialize, Deserialize, Decodable, Encodable)]
 pub struct NostimintConfigLocal {
     pub example: String,
+    /// Websocket URLs of the relays we publish signed notes to
+    #[serde(default)]
+    pub relays: Vec<String>,
+    /// How often we try to reach a relay before giving up on a note
+    #[serde(default = "default_publish_attempts")]
+    pub publish_attempts: u32,
+    /// Which notes we veto
+    #[serde(default)]
+    pub policy: NotePolicyConfig,
+    /// Address our embedded nostr relay listens on, such as `0.0.0.0:4848`,
+    /// no relay is served if unset
+    #[serde(default)]
+    pub relay_bind: Option<String>,
+}
+
+fn default_publish_attempts() -> u32 {
+    5
 }
 
 /// Will be the same for every federation member
@@ -62,8 +173,27 @@ pub struct NostimintConfigLocal {
 pub struct NostimintConfigConsensus {
     /// Example federation threshold signing key
     pub public_key_set: PublicKeySet,
+    /// Federation nostr key all notes are threshold-signed with
+    pub frost_key: FrostPublicKeySet,
     /// Will be the same for all peers
     pub tx_fee: Amount,
+    /// Fee for each note the federation signs
+    pub note_fee: Amount,
+    /// Fee for each NIP-05 name the federation registers
+    pub name_fee: Amount,
+    /// Max seconds between a note request's time and the federation's time
+    pub timestamp_tolerance: u64,
+    /// Event kinds the federation will sign
+    pub allowed_kinds: BTreeSet<u64>,
+    /// Limits how many notes an account can get signed
+    pub note_quota: NoteQuota,
+    /// Seconds after which guardians vote to end a session
+    pub session_length: u64,
+    /// Sessions after which unsigned note requests expire and are pruned
+    pub expiry_sessions: u64,
+    /// Key that can print money, must be unset in production
+    #[serde(default)]
+    pub issuer_key: Option<XOnlyPublicKey>,
 }
 
 /// Will be encrypted and not shared such as private key material
@@ -71,6 +201,8 @@ pub struct NostimintConfigConsensus {
 pub struct NostimintConfigPrivate {
     /// Example private key share for a single member
     pub private_key_share: SerdeSecret<SecretKeyShare>,
+    /// Our share of the federation nostr key
+    pub frost_key_share: SecretKey,
 }
 
 // Wire together the configs for this module
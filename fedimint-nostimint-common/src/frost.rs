@@ -0,0 +1,853 @@
+//! Threshold Schnorr signatures (FROST) producing BIP-340 signatures over
+//! secp256k1, so notes signed by the federation verify like any other nostr
+//! event.
+//!
+//! Peers are indexed by `PeerId + 1` since index 0 would reveal the secret.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, ensure};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::PeerId;
+use rand::RngCore;
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::{
+    schnorr, KeyPair, Message, Parity, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+/// Order of the secp256k1 group minus two, used to invert scalars
+const ORDER_MINUS_TWO: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x3f,
+];
+
+const BINDING_TAG: &str = "nostimint/frost/binding";
+const CHALLENGE_TAG: &str = "BIP0340/challenge";
+const DKG_PROOF_TAG: &str = "nostimint/dkg/proof";
+const DKG_SHARE_TAG: &str = "nostimint/dkg/share";
+
+/// Public half of the federation's FROST key, the same for every peer
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct FrostPublicKeySet {
+    /// Number of signature shares needed to create a signature
+    pub threshold: u32,
+    /// The federation's nostr public key (always has an even y coordinate)
+    pub group_key: XOnlyPublicKey,
+    /// Verification keys for the secret share of every peer
+    pub public_shares: BTreeMap<PeerId, PublicKey>,
+}
+
+impl FrostPublicKeySet {
+    /// Number of signature shares needed to create a signature
+    pub fn threshold(&self) -> usize {
+        self.threshold as usize
+    }
+
+    /// The federation's public key as used by nostr
+    pub fn nostr_public_key(&self) -> nostr_sdk::secp256k1::XOnlyPublicKey {
+        nostr_sdk::secp256k1::XOnlyPublicKey::from_slice(&self.group_key.serialize())
+            .expect("same curve")
+    }
+
+    /// Finds the peer the secret share belongs to
+    pub fn peer_of(&self, secret_share: &SecretKey) -> Option<PeerId> {
+        let public_share = PublicKey::from_secret_key(&Secp256k1::new(), secret_share);
+        self.public_shares
+            .iter()
+            .find(|(_, share)| **share == public_share)
+            .map(|(peer, _)| *peer)
+    }
+}
+
+/// Public commitments to the pair of secret nonces a peer signs with
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct NonceCommitment {
+    pub hiding: PublicKey,
+    pub binding: PublicKey,
+}
+
+/// Secret nonces that must never be used for more than one signature
+#[derive(Debug, Clone)]
+pub struct SecretNonce {
+    hiding: SecretKey,
+    binding: SecretKey,
+}
+
+impl SecretNonce {
+    pub fn random() -> SecretNonce {
+        SecretNonce {
+            hiding: random_scalar(),
+            binding: random_scalar(),
+        }
+    }
+
+    pub fn commitment(&self) -> NonceCommitment {
+        let secp = Secp256k1::new();
+        NonceCommitment {
+            hiding: PublicKey::from_secret_key(&secp, &self.hiding),
+            binding: PublicKey::from_secret_key(&secp, &self.binding),
+        }
+    }
+}
+
+/// A single peer's share of a Schnorr signature
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FrostSignatureShare(pub [u8; 32]);
+
+impl Encodable for FrostSignatureShare {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        writer.write_all(&self.0)?;
+        Ok(self.0.len())
+    }
+}
+
+impl Decodable for FrostSignatureShare {
+    fn consensus_decode<R: std::io::Read>(
+        r: &mut R,
+        _modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 32];
+        r.read_exact(&mut bytes).map_err(DecodeError::from_err)?;
+        Ok(FrostSignatureShare(bytes))
+    }
+}
+
+/// What a peer publishes to all others during distributed key generation
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct DkgCommitment {
+    /// Key the shares dealt to this peer are encrypted to
+    pub encryption_key: PublicKey,
+    /// Commitments to the coefficients of the peer's secret polynomial
+    pub coefficients: Vec<PublicKey>,
+    /// Proves knowledge of the constant term, preventing rogue-key attacks
+    pub proof: schnorr::Signature,
+}
+
+/// A share dealt to a single peer, encrypted to its DKG encryption key
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct EncryptedShare(pub Vec<u8>);
+
+/// Our part in a Pedersen distributed key generation: each peer deals shares
+/// of a random polynomial and the federation key is the sum of all of them,
+/// so no single peer ever learns the secret key.
+pub struct DkgDealer {
+    peer: PeerId,
+    coefficients: Vec<SecretKey>,
+    encryption_key: SecretKey,
+}
+
+impl DkgDealer {
+    pub fn new(peer: PeerId, threshold: usize) -> DkgDealer {
+        DkgDealer {
+            peer,
+            coefficients: (0..threshold).map(|_| random_scalar()).collect(),
+            encryption_key: random_scalar(),
+        }
+    }
+
+    pub fn commitment(&self) -> DkgCommitment {
+        let secp = Secp256k1::new();
+        let constant_term = KeyPair::from_secret_key(&secp, &self.coefficients[0]);
+        let coefficients = self
+            .coefficients
+            .iter()
+            .map(|coefficient| PublicKey::from_secret_key(&secp, coefficient))
+            .collect::<Vec<_>>();
+
+        DkgCommitment {
+            encryption_key: PublicKey::from_secret_key(&secp, &self.encryption_key),
+            proof: secp.sign_schnorr_no_aux_rand(
+                &proof_message(self.peer, &coefficients[0]),
+                &constant_term,
+            ),
+            coefficients,
+        }
+    }
+
+    /// Deals a share of our polynomial to every peer
+    pub fn encrypted_shares(
+        &self,
+        commitments: &BTreeMap<PeerId, DkgCommitment>,
+    ) -> BTreeMap<PeerId, EncryptedShare> {
+        commitments
+            .iter()
+            .map(|(&peer, commitment)| {
+                let share = evaluate_polynomial(&self.coefficients, peer);
+                let mask = share_mask(
+                    &self.encryption_key,
+                    &commitment.encryption_key,
+                    self.peer,
+                    peer,
+                );
+                (peer, EncryptedShare(xor(&share.secret_bytes(), &mask)))
+            })
+            .collect()
+    }
+
+    /// Verifies what every dealer published and dealt to us, then derives the
+    /// federation key and our share of it
+    pub fn finish(
+        self,
+        commitments: &BTreeMap<PeerId, DkgCommitment>,
+        shares: &BTreeMap<PeerId, EncryptedShare>,
+    ) -> anyhow::Result<(FrostPublicKeySet, SecretKey)> {
+        let secp = Secp256k1::new();
+        let threshold = self.coefficients.len();
+        ensure!(
+            commitments.keys().eq(shares.keys()),
+            "Missing shares from some dealers"
+        );
+
+        let mut secret_share: Option<SecretKey> = None;
+        for (&dealer, commitment) in commitments {
+            ensure!(
+                commitment.coefficients.len() == threshold,
+                "Peer {dealer} committed to a polynomial of the wrong degree"
+            );
+            secp.verify_schnorr(
+                &commitment.proof,
+                &proof_message(dealer, &commitment.coefficients[0]),
+                &commitment.coefficients[0].x_only_public_key().0,
+            )
+            .map_err(|_| anyhow::format_err!("Peer {dealer} sent an invalid proof of knowledge"))?;
+
+            let mask = share_mask(
+                &self.encryption_key,
+                &commitment.encryption_key,
+                dealer,
+                self.peer,
+            );
+            let share = SecretKey::from_slice(&xor(&shares[&dealer].0, &mask))?;
+            ensure!(
+                PublicKey::from_secret_key(&secp, &share)
+                    == evaluate_commitments(&commitment.coefficients, self.peer)?,
+                "Peer {dealer} dealt us an invalid share"
+            );
+
+            secret_share = Some(match secret_share {
+                Some(sum) => sum.add_tweak(&to_scalar(&share))?,
+                None => share,
+            });
+        }
+
+        let constant_terms = commitments
+            .values()
+            .map(|commitment| &commitment.coefficients[0])
+            .collect::<Vec<_>>();
+        let group_key = PublicKey::combine_keys(&constant_terms)?;
+
+        let mut public_shares = BTreeMap::new();
+        for &peer in commitments.keys() {
+            let dealt = commitments
+                .values()
+                .map(|commitment| evaluate_commitments(&commitment.coefficients, peer))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let public_share = PublicKey::combine_keys(&dealt.iter().collect::<Vec<_>>())?;
+            public_shares.insert(peer, public_share);
+        }
+
+        let Some(secret_share) = secret_share else {
+            bail!("No peer dealt any shares");
+        };
+        Ok(normalize(threshold, group_key, public_shares, secret_share))
+    }
+}
+
+/// Commits a dealer's proof of knowledge to its identity
+fn proof_message(dealer: PeerId, constant_term: &PublicKey) -> Message {
+    let mut engine = tagged_engine(DKG_PROOF_TAG);
+    engine.input(&index(dealer).to_be_bytes());
+    engine.input(&constant_term.serialize());
+    Message::from_slice(&sha256::Hash::from_engine(engine).into_inner()).expect("32 bytes")
+}
+
+/// One-time pad for the share `dealer` deals to `recipient`, derived from
+/// their DKG encryption keys
+fn share_mask(
+    our_key: &SecretKey,
+    their_key: &PublicKey,
+    dealer: PeerId,
+    recipient: PeerId,
+) -> [u8; 32] {
+    let mut engine = tagged_engine(DKG_SHARE_TAG);
+    engine.input(&SharedSecret::new(their_key, our_key).secret_bytes());
+    engine.input(&index(dealer).to_be_bytes());
+    engine.input(&index(recipient).to_be_bytes());
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+fn xor(data: &[u8], mask: &[u8; 32]) -> Vec<u8> {
+    data.iter().zip(mask).map(|(a, b)| a ^ b).collect()
+}
+
+/// BIP-340 only knows x-only keys with an even y coordinate, so if the group
+/// key is odd every peer negates its share, which negates the group key.
+fn normalize(
+    threshold: usize,
+    group_key: PublicKey,
+    public_shares: BTreeMap<PeerId, PublicKey>,
+    secret_share: SecretKey,
+) -> (FrostPublicKeySet, SecretKey) {
+    let secp = Secp256k1::new();
+    let (x_only, parity) = group_key.x_only_public_key();
+
+    let (public_shares, secret_share) = if parity == Parity::Odd {
+        (
+            public_shares
+                .into_iter()
+                .map(|(peer, share)| (peer, share.negate(&secp)))
+                .collect(),
+            secret_share.negate(),
+        )
+    } else {
+        (public_shares, secret_share)
+    };
+
+    let key_set = FrostPublicKeySet {
+        threshold: threshold as u32,
+        group_key: x_only,
+        public_shares,
+    };
+    (key_set, secret_share)
+}
+
+/// Creates our signature share over `message` for the signing set given by
+/// `commitments`, consuming the nonce so it cannot be reused
+pub fn sign(
+    key_set: &FrostPublicKeySet,
+    peer: PeerId,
+    secret_share: &SecretKey,
+    nonce: SecretNonce,
+    message: &[u8; 32],
+    commitments: &BTreeMap<PeerId, NonceCommitment>,
+) -> anyhow::Result<FrostSignatureShare> {
+    ensure!(
+        commitments.get(&peer) == Some(&nonce.commitment()),
+        "Our nonce is not part of the signing set"
+    );
+    let session = SigningSession::new(key_set, message, commitments)?;
+
+    let rho = &session.binding_factors[&peer];
+    let mut nonce_sum = nonce
+        .hiding
+        .add_tweak(&to_scalar(&mul(nonce.binding, rho)))?;
+    if session.negate_nonces {
+        nonce_sum = nonce_sum.negate();
+    }
+    let lambda = lagrange_coefficient(peer, commitments.keys());
+    let key_part = mul(mul(*secret_share, &lambda), &session.challenge);
+
+    Ok(to_share(nonce_sum.add_tweak(&to_scalar(&key_part))?))
+}
+
+/// Verifies a peer's signature share before it is combined
+pub fn verify_share(
+    key_set: &FrostPublicKeySet,
+    peer: PeerId,
+    share: &FrostSignatureShare,
+    message: &[u8; 32],
+    commitments: &BTreeMap<PeerId, NonceCommitment>,
+) -> anyhow::Result<()> {
+    let secp = Secp256k1::new();
+    let session = SigningSession::new(key_set, message, commitments)?;
+    let (Some(commitment), Some(public_share)) =
+        (commitments.get(&peer), key_set.public_shares.get(&peer))
+    else {
+        bail!("Peer {peer} is not part of the signing set");
+    };
+
+    let rho = &session.binding_factors[&peer];
+    let mut nonce_point = commitment
+        .hiding
+        .combine(&commitment.binding.mul_tweak(&secp, &to_scalar(rho))?)?;
+    if session.negate_nonces {
+        nonce_point = nonce_point.negate(&secp);
+    }
+    let lambda = lagrange_coefficient(peer, commitments.keys());
+    let key_point = public_share.mul_tweak(&secp, &to_scalar(&mul(lambda, &session.challenge)))?;
+    let expected = nonce_point.combine(&key_point)?;
+
+    let actual = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&share.0)?);
+    ensure!(actual == expected, "Signature share is invalid");
+    Ok(())
+}
+
+/// Combines the shares of the whole signing set into a BIP-340 signature and
+/// checks it against the federation's key
+pub fn combine(
+    key_set: &FrostPublicKeySet,
+    message: &[u8; 32],
+    commitments: &BTreeMap<PeerId, NonceCommitment>,
+    shares: &BTreeMap<PeerId, FrostSignatureShare>,
+) -> anyhow::Result<schnorr::Signature> {
+    ensure!(
+        shares.keys().eq(commitments.keys()),
+        "Shares do not match the signing set"
+    );
+    let session = SigningSession::new(key_set, message, commitments)?;
+
+    let mut shares = shares.values();
+    let Some(first) = shares.next() else {
+        bail!("No signature shares to combine");
+    };
+    let first = SecretKey::from_slice(&first.0)?;
+    let sum = shares.try_fold(first, |sum, share| -> anyhow::Result<SecretKey> {
+        Ok(sum.add_tweak(&Scalar::from_be_bytes(share.0)?)?)
+    })?;
+
+    let mut bytes = [0; 64];
+    bytes[..32].copy_from_slice(&session.nonce.serialize());
+    bytes[32..].copy_from_slice(&sum.secret_bytes());
+    let signature = schnorr::Signature::from_slice(&bytes)?;
+
+    Secp256k1::new().verify_schnorr(
+        &signature,
+        &Message::from_slice(message)?,
+        &key_set.group_key,
+    )?;
+    Ok(signature)
+}
+
+/// Values every signer derives from the message and the signing set
+struct SigningSession {
+    binding_factors: BTreeMap<PeerId, SecretKey>,
+    nonce: XOnlyPublicKey,
+    negate_nonces: bool,
+    challenge: SecretKey,
+}
+
+impl SigningSession {
+    fn new(
+        key_set: &FrostPublicKeySet,
+        message: &[u8; 32],
+        commitments: &BTreeMap<PeerId, NonceCommitment>,
+    ) -> anyhow::Result<SigningSession> {
+        ensure!(
+            commitments.len() == key_set.threshold(),
+            "Signing set must contain exactly {} peers",
+            key_set.threshold()
+        );
+        let secp = Secp256k1::new();
+
+        let mut encoded_commitments = vec![];
+        for (peer, commitment) in commitments {
+            encoded_commitments.extend(index(*peer).to_be_bytes());
+            encoded_commitments.extend(commitment.hiding.serialize());
+            encoded_commitments.extend(commitment.binding.serialize());
+        }
+
+        let mut binding_factors = BTreeMap::new();
+        let mut nonce_points = vec![];
+        for (peer, commitment) in commitments {
+            let mut engine = tagged_engine(BINDING_TAG);
+            engine.input(&index(*peer).to_be_bytes());
+            engine.input(message);
+            engine.input(&encoded_commitments);
+            let rho = hash_to_scalar(engine);
+
+            let binding = commitment.binding.mul_tweak(&secp, &to_scalar(&rho))?;
+            nonce_points.push(commitment.hiding.combine(&binding)?);
+            binding_factors.insert(*peer, rho);
+        }
+        let nonce_point = PublicKey::combine_keys(&nonce_points.iter().collect::<Vec<_>>())?;
+        let (nonce, parity) = nonce_point.x_only_public_key();
+
+        let mut engine = tagged_engine(CHALLENGE_TAG);
+        engine.input(&nonce.serialize());
+        engine.input(&key_set.group_key.serialize());
+        engine.input(message);
+
+        Ok(SigningSession {
+            binding_factors,
+            nonce,
+            negate_nonces: parity == Parity::Odd,
+            challenge: hash_to_scalar(engine),
+        })
+    }
+}
+
+/// Lagrange coefficient of `peer` for interpolating at zero over `signers`
+fn lagrange_coefficient<'a>(peer: PeerId, signers: impl Iterator<Item = &'a PeerId>) -> SecretKey {
+    let mut numerator = scalar_from_u64(1);
+    let mut denominator = scalar_from_u64(1);
+
+    for signer in signers.filter(|signer| **signer != peer) {
+        numerator = mul(numerator, &scalar_from_u64(index(*signer)));
+        let difference = if index(*signer) > index(peer) {
+            scalar_from_u64(index(*signer) - index(peer))
+        } else {
+            scalar_from_u64(index(peer) - index(*signer)).negate()
+        };
+        denominator = mul(denominator, &difference);
+    }
+
+    mul(numerator, &invert(&denominator))
+}
+
+fn evaluate_polynomial(coefficients: &[SecretKey], peer: PeerId) -> SecretKey {
+    let x = scalar_from_u64(index(peer));
+    let mut coefficients = coefficients.iter().rev();
+    let highest = *coefficients.next().expect("polynomial has a constant term");
+    coefficients.fold(highest, |acc, coefficient| {
+        mul(acc, &x)
+            .add_tweak(&to_scalar(coefficient))
+            .expect("zero with negligible probability")
+    })
+}
+
+/// Evaluates a polynomial in the exponent, giving the public key of a share
+fn evaluate_commitments(coefficients: &[PublicKey], peer: PeerId) -> anyhow::Result<PublicKey> {
+    let secp = Secp256k1::new();
+    let x = to_scalar(&scalar_from_u64(index(peer)));
+    let mut coefficients = coefficients.iter().rev();
+    let highest = *coefficients.next().expect("polynomial has a constant term");
+    coefficients.try_fold(highest, |acc, coefficient| -> anyhow::Result<PublicKey> {
+        Ok(acc.mul_tweak(&secp, &x)?.combine(coefficient)?)
+    })
+}
+
+fn index(peer: PeerId) -> u64 {
+    peer.to_usize() as u64 + 1
+}
+
+/// Inverts a scalar using Fermat's little theorem
+fn invert(scalar: &SecretKey) -> SecretKey {
+    let mut result = scalar_from_u64(1);
+    for byte in ORDER_MINUS_TWO {
+        for bit in (0..8).rev() {
+            result = mul(result, &result);
+            if (byte >> bit) & 1 == 1 {
+                result = mul(result, scalar);
+            }
+        }
+    }
+    result
+}
+
+/// Multiplies two scalars, the product of non-zero scalars is never zero
+fn mul(a: SecretKey, b: &SecretKey) -> SecretKey {
+    a.mul_tweak(&to_scalar(b)).expect("group order is prime")
+}
+
+fn to_scalar(key: &SecretKey) -> Scalar {
+    Scalar::from_be_bytes(key.secret_bytes()).expect("secret keys are valid scalars")
+}
+
+fn to_share(key: SecretKey) -> FrostSignatureShare {
+    FrostSignatureShare(key.secret_bytes())
+}
+
+fn scalar_from_u64(value: u64) -> SecretKey {
+    let mut bytes = [0; 32];
+    bytes[24..].copy_from_slice(&value.to_be_bytes());
+    SecretKey::from_slice(&bytes).expect("non-zero and smaller than the group order")
+}
+
+fn random_scalar() -> SecretKey {
+    loop {
+        let mut bytes = [0; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        if let Ok(key) = SecretKey::from_slice(&bytes) {
+            return key;
+        }
+    }
+}
+
+fn tagged_engine(tag: &str) -> sha256::HashEngine {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::HashEngine::default();
+    engine.input(&tag);
+    engine.input(&tag);
+    engine
+}
+
+fn hash_to_scalar(engine: sha256::HashEngine) -> SecretKey {
+    SecretKey::from_slice(&sha256::Hash::from_engine(engine).into_inner())
+        .expect("hash is a valid scalar with overwhelming probability")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Dealt = BTreeMap<PeerId, BTreeMap<PeerId, EncryptedShare>>;
+
+    /// Publishes the commitments of `peers` dealers and the shares each dealt
+    /// to every peer
+    fn deal(
+        peers: u16,
+        threshold: usize,
+    ) -> (
+        BTreeMap<PeerId, DkgDealer>,
+        BTreeMap<PeerId, DkgCommitment>,
+        Dealt,
+    ) {
+        let dealers = (0..peers)
+            .map(|peer| {
+                (
+                    PeerId::from(peer),
+                    DkgDealer::new(PeerId::from(peer), threshold),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        let commitments = dealers
+            .iter()
+            .map(|(&peer, dealer)| (peer, dealer.commitment()))
+            .collect::<BTreeMap<_, _>>();
+        let dealt = dealers
+            .iter()
+            .map(|(&peer, dealer)| (peer, dealer.encrypted_shares(&commitments)))
+            .collect();
+        (dealers, commitments, dealt)
+    }
+
+    /// The shares every dealer dealt to `peer`
+    fn shares_for(dealt: &Dealt, peer: PeerId) -> BTreeMap<PeerId, EncryptedShare> {
+        dealt
+            .iter()
+            .map(|(&dealer, shares)| (dealer, shares[&peer].clone()))
+            .collect()
+    }
+
+    /// Runs the key generation between `peers` peers, returning the federation
+    /// key and each peer's share of it
+    fn dkg(
+        peers: u16,
+        threshold: usize,
+    ) -> anyhow::Result<(FrostPublicKeySet, BTreeMap<PeerId, SecretKey>)> {
+        let (dealers, commitments, dealt) = deal(peers, threshold);
+
+        let mut key_set = None;
+        let mut secret_shares = BTreeMap::new();
+        for (peer, dealer) in dealers {
+            let (our_key_set, secret_share) =
+                dealer.finish(&commitments, &shares_for(&dealt, peer))?;
+            ensure!(
+                *key_set.get_or_insert(our_key_set.clone()) == our_key_set,
+                "Peer {peer} derived a different federation key"
+            );
+            secret_shares.insert(peer, secret_share);
+        }
+        Ok((key_set.expect("at least one peer"), secret_shares))
+    }
+
+    /// Signs `message` with the shares of `signers`, checking every share
+    fn sign_with(
+        key_set: &FrostPublicKeySet,
+        secret_shares: &BTreeMap<PeerId, SecretKey>,
+        signers: &[u16],
+        message: &[u8; 32],
+    ) -> anyhow::Result<schnorr::Signature> {
+        let nonces = signers
+            .iter()
+            .map(|&peer| (PeerId::from(peer), SecretNonce::random()))
+            .collect::<BTreeMap<_, _>>();
+        let commitments = nonces
+            .iter()
+            .map(|(&peer, nonce)| (peer, nonce.commitment()))
+            .collect();
+
+        let mut shares = BTreeMap::new();
+        for (peer, nonce) in nonces {
+            let share = sign(
+                key_set,
+                peer,
+                &secret_shares[&peer],
+                nonce,
+                message,
+                &commitments,
+            )?;
+            verify_share(key_set, peer, &share, message, &commitments)?;
+            shares.insert(peer, share);
+        }
+        combine(key_set, message, &commitments, &shares)
+    }
+
+    fn message(text: &str) -> [u8; 32] {
+        sha256::Hash::hash(text.as_bytes()).into_inner()
+    }
+
+    #[test]
+    fn signs_with_any_threshold_of_peers() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        for (peers, threshold, signing_sets) in [
+            (1, 1, vec![vec![0]]),
+            (3, 2, vec![vec![0, 1], vec![0, 2], vec![1, 2]]),
+            (
+                5,
+                3,
+                vec![vec![0, 1, 2], vec![0, 2, 4], vec![1, 3, 4], vec![2, 3, 4]],
+            ),
+        ] {
+            let (key_set, secret_shares) = dkg(peers, threshold)?;
+            for signers in signing_sets {
+                let message = message(&format!("signed by {signers:?}"));
+                let signature = sign_with(&key_set, &secret_shares, &signers, &message)?;
+
+                // Any BIP-340 verifier accepts it for the federation's key
+                secp.verify_schnorr(
+                    &signature,
+                    &Message::from_slice(&message)?,
+                    &key_set.group_key,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_tampered_signature_shares() -> anyhow::Result<()> {
+        let (key_set, secret_shares) = dkg(3, 2)?;
+        let message = message("tampered");
+        let nonces = [0u16, 2]
+            .map(|peer| (PeerId::from(peer), SecretNonce::random()))
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let commitments = nonces
+            .iter()
+            .map(|(&peer, nonce)| (peer, nonce.commitment()))
+            .collect();
+
+        let mut shares = BTreeMap::new();
+        for (peer, nonce) in nonces {
+            let share = sign(
+                &key_set,
+                peer,
+                &secret_shares[&peer],
+                nonce,
+                &message,
+                &commitments,
+            )?;
+            shares.insert(peer, share);
+        }
+
+        let peer = PeerId::from(2);
+        let mut tampered = shares[&peer];
+        tampered.0[31] ^= 1;
+        assert!(verify_share(&key_set, peer, &tampered, &message, &commitments).is_err());
+        // A valid share doesn't verify as another peer's either
+        assert!(verify_share(
+            &key_set,
+            PeerId::from(0),
+            &shares[&peer],
+            &message,
+            &commitments
+        )
+        .is_err());
+
+        shares.insert(peer, tampered);
+        assert!(combine(&key_set, &message, &commitments, &shares).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn only_signs_for_complete_signing_sets() -> anyhow::Result<()> {
+        let (key_set, secret_shares) = dkg(3, 2)?;
+        let message = message("incomplete");
+        let nonce = SecretNonce::random();
+        let commitments = BTreeMap::from([(PeerId::from(0), nonce.commitment())]);
+
+        assert!(sign(
+            &key_set,
+            PeerId::from(0),
+            &secret_shares[&PeerId::from(0)],
+            nonce,
+            &message,
+            &commitments,
+        )
+        .is_err());
+        assert!(combine(&key_set, &message, &BTreeMap::new(), &BTreeMap::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn peers_agree_on_the_federation_key() -> anyhow::Result<()> {
+        let secp = Secp256k1::new();
+        let (key_set, secret_shares) = dkg(4, 3)?;
+
+        assert_eq!(key_set.threshold(), 3);
+        assert_eq!(key_set.public_shares.len(), 4);
+        for (&peer, secret_share) in &secret_shares {
+            assert_eq!(
+                key_set.public_shares[&peer],
+                PublicKey::from_secret_key(&secp, secret_share)
+            );
+            assert_eq!(key_set.peer_of(secret_share), Some(peer));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_shares() -> anyhow::Result<()> {
+        let (mut dealers, commitments, mut dealt) = deal(3, 2);
+        let (dealer, victim) = (PeerId::from(0), PeerId::from(1));
+        dealt
+            .get_mut(&dealer)
+            .and_then(|shares| shares.get_mut(&victim))
+            .expect("share was dealt")
+            .0[0] ^= 1;
+
+        let error = dealers
+            .remove(&victim)
+            .expect("victim is a dealer")
+            .finish(&commitments, &shares_for(&dealt, victim))
+            .expect_err("share was tampered with");
+        assert!(error.to_string().contains("invalid share"), "{error}");
+
+        // The other peers are unaffected
+        let peer = PeerId::from(2);
+        dealers
+            .remove(&peer)
+            .expect("peer is a dealer")
+            .finish(&commitments, &shares_for(&dealt, peer))?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_proofs_of_knowledge() {
+        let (mut dealers, mut commitments, dealt) = deal(3, 2);
+
+        // A proof only holds for the dealer that made it
+        let stolen = commitments[&PeerId::from(1)].proof;
+        commitments
+            .get_mut(&PeerId::from(0))
+            .expect("peer is a dealer")
+            .proof = stolen;
+
+        let peer = PeerId::from(2);
+        let error = dealers
+            .remove(&peer)
+            .expect("peer is a dealer")
+            .finish(&commitments, &shares_for(&dealt, peer))
+            .expect_err("proof is for another dealer");
+        assert!(
+            error.to_string().contains("invalid proof of knowledge"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn rejects_missing_dealers() {
+        let (mut dealers, commitments, dealt) = deal(3, 2);
+        let peer = PeerId::from(2);
+        let mut shares = shares_for(&dealt, peer);
+        shares.remove(&PeerId::from(0));
+
+        assert!(dealers
+            .remove(&peer)
+            .expect("peer is a dealer")
+            .finish(&commitments, &shares)
+            .is_err());
+    }
+
+    #[test]
+    fn inverts_scalars() {
+        for value in [1, 2, 3, 1000, u64::MAX] {
+            let scalar = scalar_from_u64(value);
+            assert_eq!(mul(scalar, &invert(&scalar)), scalar_from_u64(1));
+        }
+    }
+}
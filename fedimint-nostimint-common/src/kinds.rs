@@ -0,0 +1,346 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::str::FromStr;
+
+use anyhow::{bail, ensure};
+use fedimint_core::Amount;
+use nostr_sdk::secp256k1::XOnlyPublicKey;
+use nostr_sdk::EventId;
+
+use crate::{Event, NoteRequest, MAX_EVENT_SIZE, MAX_EVENT_TAGS};
+
+/// Profile metadata, NIP-01
+pub const METADATA: u64 = 0;
+/// Short text note, NIP-01
+pub const TEXT_NOTE: u64 = 1;
+/// Follow list, NIP-02
+pub const CONTACT_LIST: u64 = 3;
+/// Reaction to another event, NIP-25
+pub const REACTION: u64 = 7;
+/// Request to zap a user or their note, sent along with the payment, NIP-57
+pub const ZAP_REQUEST: u64 = 9734;
+/// Receipt of a zap published by the zapper, NIP-57
+pub const ZAP_RECEIPT: u64 = 9735;
+/// Long-form article, NIP-23
+pub const LONG_FORM: u64 = 30023;
+
+/// Kinds of which relays only keep the latest event per pubkey, NIP-01
+pub const REPLACEABLE: Range<u64> = 10000..20000;
+/// Kinds of which relays only keep the latest event per pubkey and `d` tag
+pub const PARAMETERIZED_REPLACEABLE: Range<u64> = 30000..40000;
+
+/// Room the fields of a signed event other than its content and tags take up
+const EVENT_OVERHEAD: usize = 512;
+
+/// Kinds the federation signs unless its config says otherwise, its own
+/// metadata is only signed once guardians approved it
+pub fn default_allowed_kinds() -> BTreeSet<u64> {
+    BTreeSet::from([TEXT_NOTE, CONTACT_LIST, REACTION, LONG_FORM])
+}
+
+/// Whether relays keep a single event of this kind for the federation's key
+///
+/// All accounts share that key, so only the first account to get such an event
+/// signed can replace it later, others would replace its event.
+pub fn has_single_owner(kind: u64) -> bool {
+    kind == CONTACT_LIST || REPLACEABLE.contains(&kind)
+}
+
+/// The `d` tag of an account's parameterized replaceable event, so relays keep
+/// the latest event of each account rather than of the federation's key
+pub fn namespaced_identifier(account: secp256k1::XOnlyPublicKey, identifier: &str) -> String {
+    format!("{account}:{identifier}")
+}
+
+/// Prefixes the `d` tags of a parameterized replaceable event with the account,
+/// other kinds and tags already prefixed are left as they are
+pub fn namespace_identifiers(
+    kind: u64,
+    tags: Vec<Vec<String>>,
+    account: secp256k1::XOnlyPublicKey,
+) -> Vec<Vec<String>> {
+    if !PARAMETERIZED_REPLACEABLE.contains(&kind) {
+        return tags;
+    }
+    let prefix = namespaced_identifier(account, "");
+    tags.into_iter()
+        .map(|mut tag| {
+            if let [name, identifier, ..] = tag.as_mut_slice() {
+                if name == "d" && !identifier.starts_with(&prefix) {
+                    *identifier = namespaced_identifier(account, identifier);
+                }
+            }
+            tag
+        })
+        .collect()
+}
+
+/// Checks a note request of an account doesn't take the place of another
+/// account's parameterized replaceable events
+pub fn validate_namespace(
+    request: &NoteRequest,
+    account: secp256k1::XOnlyPublicKey,
+) -> anyhow::Result<()> {
+    if PARAMETERIZED_REPLACEABLE.contains(&request.kind) {
+        let prefix = namespaced_identifier(account, "");
+        ensure!(
+            tag_values(request, "d").all(|identifier| identifier.starts_with(&prefix)),
+            "The `d` tag needs to start with the account's key, as in `{prefix}`"
+        );
+    }
+    Ok(())
+}
+
+/// Checks a note request is allowed and well-formed for its kind
+pub fn validate(request: &NoteRequest, allowed_kinds: &BTreeSet<u64>) -> anyhow::Result<()> {
+    ensure!(
+        request.kind != METADATA,
+        "The federation profile can only be changed by its guardians"
+    );
+    ensure!(
+        request.kind != ZAP_RECEIPT,
+        "Zap receipts are only signed for zaps paid to the federation"
+    );
+    ensure!(
+        allowed_kinds.contains(&request.kind),
+        "The federation doesn't sign events of kind {}",
+        request.kind
+    );
+    ensure!(
+        request.tags.iter().all(|tag| !tag.is_empty()),
+        "Tags need a name"
+    );
+    validate_size(request)?;
+
+    match request.kind {
+        CONTACT_LIST => {
+            for tag in &request.tags {
+                let [name, pubkey, ..] = tag.as_slice() else {
+                    bail!("Contact list tags need a pubkey");
+                };
+                ensure!(name == "p", "Contact lists only contain `p` tags");
+                XOnlyPublicKey::from_str(pubkey)?;
+            }
+        }
+        REACTION => {
+            ensure!(
+                tag_values(request, "e").next().is_some(),
+                "Reactions need an `e` tag of the event reacted to"
+            );
+            for id in tag_values(request, "e") {
+                EventId::from_hex(id)?;
+            }
+        }
+        kind if PARAMETERIZED_REPLACEABLE.contains(&kind) => {
+            ensure!(
+                tag_values(request, "d").count() == 1,
+                "Parameterized replaceable events need exactly one `d` tag"
+            );
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Builds the receipt the federation signs for a zap paid into `recipient`'s
+/// account, after checking the zap request is for them and the amount paid
+///
+/// The zap is paid in ecash rather than a lightning invoice, so the receipt
+/// carries an `amount` tag instead of a `bolt11` one.
+pub fn zap_receipt(
+    zap_request: &Event,
+    recipient: secp256k1::XOnlyPublicKey,
+    amount: Amount,
+) -> anyhow::Result<NoteRequest> {
+    // Decoding an event already verified its id and signature
+    let event = &zap_request.event;
+    ensure!(
+        event.kind.as_u64() == ZAP_REQUEST,
+        "Zap requests are events of kind {ZAP_REQUEST}"
+    );
+    let request = NoteRequest {
+        kind: event.kind.as_u64(),
+        content: event.content.clone(),
+        tags: event.tags.iter().map(|tag| tag.as_vec()).collect(),
+        created_at: event.created_at.as_u64(),
+    };
+
+    let pubkeys = tag_values(&request, "p").collect::<Vec<_>>();
+    let [pubkey] = pubkeys.as_slice() else {
+        bail!("Zap requests need exactly one `p` tag of the recipient");
+    };
+    ensure!(
+        XOnlyPublicKey::from_str(pubkey)?.serialize() == recipient.serialize(),
+        "Zap request is not for the account paid"
+    );
+    if let Some(requested) = tag_values(&request, "amount").next() {
+        ensure!(
+            requested.parse::<u64>()? == amount.msats,
+            "Zap request is for {requested} msats but {} were paid",
+            amount.msats
+        );
+    }
+    let ids = tag_values(&request, "e").collect::<Vec<_>>();
+    ensure!(ids.len() <= 1, "Zap requests can have at most one `e` tag");
+
+    let mut tags = vec![vec!["p".to_string(), pubkey.to_string()]];
+    for id in ids {
+        tags.push(vec!["e".to_string(), EventId::from_hex(id)?.to_hex()]);
+    }
+    tags.push(vec!["P".to_string(), event.pubkey.to_string()]);
+    tags.push(vec!["amount".to_string(), amount.msats.to_string()]);
+    tags.push(vec!["description".to_string(), event.as_json()]);
+
+    let receipt = NoteRequest {
+        kind: ZAP_RECEIPT,
+        content: String::new(),
+        tags,
+        // Only tells receipts apart, the federation signs them at the time it
+        // agrees on
+        created_at: request.created_at,
+    };
+    validate_size(&receipt)?;
+    Ok(receipt)
+}
+
+/// Checks a note fits into a signed event relays accept
+fn validate_size(request: &NoteRequest) -> anyhow::Result<()> {
+    ensure!(
+        request.tags.len() <= MAX_EVENT_TAGS,
+        "Notes can have at most {MAX_EVENT_TAGS} tags"
+    );
+    let size = serde_json::to_string(&request.content)?.len()
+        + serde_json::to_string(&request.tags)?.len()
+        + EVENT_OVERHEAD;
+    ensure!(
+        size <= MAX_EVENT_SIZE,
+        "Notes can be at most {MAX_EVENT_SIZE} bytes"
+    );
+    Ok(())
+}
+
+/// Values of all tags of a name, tags without a value are skipped
+fn tag_values<'a>(request: &'a NoteRequest, name: &'a str) -> impl Iterator<Item = &'a str> {
+    request
+        .tags
+        .iter()
+        .filter(move |tag| tag.first().map(String::as_str) == Some(name))
+        .filter_map(|tag| tag.get(1).map(String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{EventBuilder, Keys, Kind, Tag};
+
+    use super::*;
+
+    fn request(kind: u64, tags: Vec<Vec<String>>) -> NoteRequest {
+        NoteRequest {
+            kind,
+            content: String::new(),
+            tags,
+            created_at: 1_700_000_000,
+        }
+    }
+
+    fn tag(name: &str, value: &str) -> Vec<String> {
+        vec![name.to_string(), value.to_string()]
+    }
+
+    fn account() -> secp256k1::XOnlyPublicKey {
+        let pubkey = Keys::generate().public_key().serialize();
+        secp256k1::XOnlyPublicKey::from_slice(&pubkey).expect("nostr keys are x-only keys")
+    }
+
+    #[test]
+    fn validates_each_kind() -> anyhow::Result<()> {
+        let allowed = default_allowed_kinds();
+        let pubkey = Keys::generate().public_key().to_string();
+        let id = EventBuilder::new_text_note("liked", &[])
+            .to_event(&Keys::generate())?
+            .id
+            .to_hex();
+
+        assert!(validate(&request(TEXT_NOTE, vec![]), &allowed).is_ok());
+        assert!(validate(&request(METADATA, vec![]), &allowed).is_err());
+        assert!(validate(&request(ZAP_RECEIPT, vec![]), &[ZAP_RECEIPT].into()).is_err());
+        assert!(validate(&request(10002, vec![]), &allowed).is_err());
+        assert!(validate(&request(10002, vec![]), &[10002].into()).is_ok());
+
+        assert!(validate(&request(CONTACT_LIST, vec![tag("p", &pubkey)]), &allowed).is_ok());
+        assert!(validate(&request(CONTACT_LIST, vec![tag("e", &id)]), &allowed).is_err());
+        assert!(validate(&request(CONTACT_LIST, vec![tag("p", "bob")]), &allowed).is_err());
+
+        assert!(validate(&request(REACTION, vec![tag("e", &id)]), &allowed).is_ok());
+        assert!(validate(&request(REACTION, vec![]), &allowed).is_err());
+
+        let article = |tags| request(LONG_FORM, tags);
+        assert!(validate(&article(vec![tag("d", "intro")]), &allowed).is_ok());
+        assert!(validate(&article(vec![]), &allowed).is_err());
+        assert!(validate(&article(vec![tag("d", "a"), tag("d", "b")]), &allowed).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn namespaces_identifiers_per_account() {
+        let (alice, bob) = (account(), account());
+        let tags = vec![tag("d", "intro"), tag("t", "nostr")];
+
+        let article = request(
+            LONG_FORM,
+            namespace_identifiers(LONG_FORM, tags.clone(), alice),
+        );
+        assert_eq!(article.tags[0][1], namespaced_identifier(alice, "intro"));
+        assert_eq!(article.tags[1], tag("t", "nostr"));
+        assert!(validate_namespace(&article, alice).is_ok());
+        assert!(validate_namespace(&article, bob).is_err());
+
+        // Namespacing twice or other kinds change nothing
+        assert_eq!(
+            namespace_identifiers(LONG_FORM, article.tags.clone(), alice),
+            article.tags
+        );
+        assert_eq!(namespace_identifiers(TEXT_NOTE, tags.clone(), alice), tags);
+        assert!(validate_namespace(&request(LONG_FORM, tags), alice).is_err());
+    }
+
+    #[test]
+    fn builds_zap_receipts() -> anyhow::Result<()> {
+        let sender = Keys::generate();
+        let recipient = Keys::generate().public_key();
+        let account = secp256k1::XOnlyPublicKey::from_slice(&recipient.serialize())?;
+        let zapped = EventBuilder::new_text_note("zap me", &[]).to_event(&Keys::generate())?;
+        let tags = vec![
+            Tag::parse(vec!["p".to_string(), recipient.to_string()])?,
+            Tag::parse(vec!["e".to_string(), zapped.id.to_hex()])?,
+            Tag::parse(vec!["amount".to_string(), "21000".to_string()])?,
+        ];
+        let zap_request = Event {
+            event: EventBuilder::new(Kind::from(ZAP_REQUEST), "great note", &tags)
+                .to_event(&sender)?,
+        };
+
+        let receipt = zap_receipt(&zap_request, account, Amount::from_msats(21000))?;
+        assert_eq!(receipt.kind, ZAP_RECEIPT);
+        assert_eq!(receipt.created_at, zap_request.event.created_at.as_u64());
+        assert!(receipt.tags.contains(&tag("e", &zapped.id.to_hex())));
+        assert!(receipt
+            .tags
+            .contains(&tag("P", &sender.public_key().to_string())));
+        assert!(receipt
+            .tags
+            .contains(&tag("description", &zap_request.event.as_json())));
+
+        // The payment has to match the request
+        assert!(zap_receipt(&zap_request, account, Amount::from_msats(1000)).is_err());
+        let other = secp256k1::XOnlyPublicKey::from_slice(&sender.public_key().serialize())?;
+        assert!(zap_receipt(&zap_request, other, Amount::from_msats(21000)).is_err());
+
+        // Users can't get zap receipts signed without paying a zap
+        assert!(validate(&receipt, &[ZAP_RECEIPT].into()).is_err());
+
+        Ok(())
+    }
+}
@@ -1,13 +1,16 @@
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::str;
 
+use bitcoin_hashes::{sha256, Hash as _, HashEngine};
 use config::NostimintClientConfig;
 use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
-use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::epoch::SerdeSignatureShare;
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
-use fedimint_core::{plugin_types_trait_impl_common, Amount};
-use secp256k1::{KeyPair, Secp256k1, XOnlyPublicKey};
+use fedimint_core::{plugin_types_trait_impl_common, Amount, PeerId};
+use frost::{FrostSignatureShare, NonceCommitment};
+use nostr_sdk::{EventId, Kind, Tag, Timestamp};
+use secp256k1::{schnorr, KeyPair, Message, Secp256k1, XOnlyPublicKey};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -16,22 +19,43 @@ use thiserror::Error;
 // The client and server configuration
 pub mod config;
 
+// Threshold signing of nostr events
+pub mod frost;
+
+// Event kinds the federation can sign and how each is validated
+pub mod kinds;
+
+// NIP-05 names the federation registers for accounts
+pub mod nip05;
+
 /// Unique name for this module
 pub const KIND: ModuleKind = ModuleKind::from_static_str("nostimint");
 
 /// Modules are non-compatible with older versions
-pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
-
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
-pub struct Nonce {
-    value: u64,
-}
+///
+/// 1: inputs carry the note request they pay for
+/// 2: events are encoded in binary, inputs can zap notes and register names
+/// 3: inputs carry the account's signature over their note request
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(3);
 
 #[derive(Serialize, Deserialize, Hash, Debug, Clone, PartialEq, Eq)]
 pub struct Event {
     pub event: nostr_sdk::Event,
 }
 
+impl Event {
+    /// Checks the event is authored by the federation and its threshold
+    /// signature verifies, so it can be trusted without asking guardians
+    pub fn verify_federation_signature(&self, public_key: XOnlyPublicKey) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.event.pubkey.serialize() == public_key.serialize(),
+            "Event is not authored by the federation"
+        );
+        self.event.verify()?;
+        Ok(())
+    }
+}
+
 impl AsRef<[u8]> for Event {
     fn as_ref(&self) -> &[u8] {
         self.event.id.as_bytes()
@@ -42,25 +66,360 @@ impl Decodable for Event {
     fn consensus_decode<R: std::io::Read>(
         r: &mut R,
         modules: &fedimint_core::module::registry::ModuleDecoderRegistry,
-    ) -> Result<Self, fedimint_core::encoding::DecodeError> {
-        let bytes = Vec::<u8>::consensus_decode(r, modules)?;
-        let json = String::from_utf8(bytes).unwrap();
-        let event = nostr_sdk::Event::from_json(json).unwrap();
+    ) -> Result<Self, DecodeError> {
+        let mut limited = std::io::Read::take(r, MAX_EVENT_SIZE as u64);
+        let unsigned = decode_event_fields(&mut limited, modules)?;
+        let sig = read_array::<_, 64>(&mut limited)?;
+        let sig = nostr_sdk::secp256k1::schnorr::Signature::from_slice(&sig)
+            .map_err(DecodeError::from_err)?;
+        let event = unsigned.add_signature(sig).map_err(DecodeError::from_err)?;
         Ok(Event { event })
     }
 }
 
 impl Encodable for Event {
     fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
-        self.event.as_json().as_bytes().consensus_encode(writer)
+        let event = &self.event;
+        let mut len = encode_event_fields(
+            writer,
+            &event.id,
+            &event.pubkey,
+            event.created_at,
+            event.kind,
+            &event.tags,
+            &event.content,
+        )?;
+        writer.write_all(event.sig.as_ref())?;
+        len += 64;
+        Ok(len)
+    }
+}
+
+/// The id of a nostr event, encoded as its 32 bytes
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct NoteId(pub EventId);
+
+impl Decodable for NoteId {
+    fn consensus_decode<R: std::io::Read>(
+        r: &mut R,
+        _modules: &fedimint_core::module::registry::ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let mut bytes = [0; 32];
+        r.read_exact(&mut bytes).map_err(DecodeError::from_err)?;
+        let id = EventId::from_slice(&bytes).map_err(DecodeError::from_err)?;
+        Ok(NoteId(id))
+    }
+}
+
+impl Encodable for NoteId {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        writer.write_all(self.0.as_bytes())?;
+        Ok(32)
+    }
+}
+
+/// A relay's answer to a signed note one of the guardians published
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct RelayReceipt {
+    /// The guardian that published the note
+    pub peer: PeerId,
+    /// Websocket URL of the relay
+    pub relay: String,
+    /// Whether the relay stored the note
+    pub accepted: bool,
+    /// The relay's reason, explains rejections such as `blocked: ...`
+    pub message: String,
+    /// Unix time in seconds when the relay answered
+    pub timestamp: u64,
+}
+
+/// A user's request for the federation to author a note, every peer builds
+/// the same event from it so no client can choose the pubkey or id
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct NoteRequest {
+    pub kind: u64,
+    pub content: String,
+    /// NIP-01 tags such as `["e", <event id>]`
+    pub tags: Vec<Vec<String>>,
+    /// When the client made the request, stale or future-dated requests are
+    /// rejected once the federation agreed on its own time
+    pub created_at: u64,
+}
+
+impl NoteRequest {
+    /// Builds the unsigned event authored by the federation key
+    pub fn to_unsigned_event(
+        &self,
+        pubkey: nostr_sdk::secp256k1::XOnlyPublicKey,
+        created_at: u64,
+    ) -> anyhow::Result<UnsignedEvent> {
+        let tags = self
+            .tags
+            .iter()
+            .map(|tag| Tag::parse(tag.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let created_at = Timestamp::from(created_at);
+        let kind = Kind::from(self.kind);
+        let id = EventId::new(&pubkey, created_at, &kind, &tags, &self.content);
+
+        Ok(UnsignedEvent {
+            event: nostr_sdk::UnsignedEvent {
+                id,
+                pubkey,
+                created_at,
+                kind,
+                tags,
+                content: self.content.clone(),
+            },
+        })
+    }
+
+    /// Signs the request with the key of the account requesting the note
+    pub fn sign(self, key: &KeyPair) -> SignedNoteRequest {
+        let signature =
+            Secp256k1::new().sign_schnorr_with_aux_rand(&self.message(), key, &rand::random());
+        SignedNoteRequest {
+            request: self,
+            signature,
+        }
+    }
+
+    /// The hash accounts sign, tagged so the signature is only valid for this
+    fn message(&self) -> Message {
+        let mut encoded = vec![];
+        self.consensus_encode(&mut encoded)
+            .expect("writing to a vec can't fail");
+        let tag = sha256::Hash::hash(b"nostimint/note-request");
+        let mut engine = sha256::Hash::engine();
+        engine.input(&tag[..]);
+        engine.input(&tag[..]);
+        engine.input(&encoded);
+        Message::from_slice(&sha256::Hash::from_engine(engine)[..]).expect("32 bytes")
+    }
+}
+
+/// A note request signed by the account requesting it, guardians only queue
+/// requests whose signature is valid for the paying account
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SignedNoteRequest {
+    pub request: NoteRequest,
+    /// The account's BIP-340 signature over the request
+    pub signature: schnorr::Signature,
+}
+
+impl SignedNoteRequest {
+    /// Checks `account` signed the request
+    pub fn verify(&self, account: &XOnlyPublicKey) -> anyhow::Result<()> {
+        Secp256k1::verification_only()
+            .verify_schnorr(&self.signature, &self.request.message(), account)
+            .map_err(|_| anyhow::anyhow!("Note request is not signed by the account"))
+    }
+}
+
+/// Kind-0 metadata of the federation's npub, set by a threshold of guardians
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct FederationProfile {
+    pub name: String,
+    pub about: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    /// NIP-05 identifier such as `_@federation.example`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nip05: Option<String>,
+}
+
+impl FederationProfile {
+    /// The metadata note guardians approved, authored at the agreed time
+    pub fn to_note_request(&self, created_at: u64) -> NoteRequest {
+        NoteRequest {
+            kind: kinds::METADATA,
+            content: serde_json::to_string(self).expect("profiles serialize"),
+            tags: vec![],
+            created_at,
+        }
+    }
+
+    /// The profile a metadata note was built from, `None` for other notes
+    pub fn from_note_request(request: &NoteRequest) -> Option<Self> {
+        if request.kind != kinds::METADATA {
+            return None;
+        }
+        serde_json::from_str(&request.content).ok()
+    }
+}
+
+/// Where a note request is in its lifecycle
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum NoteStatus {
+    /// Paid for, guardians are voting on its time or vetoing it
+    Pending,
+    /// Built at the agreed time, guardians are threshold-signing it
+    Signing(UnsignedEvent),
+    /// Signed by the federation
+    Signed(Event),
+    /// Won't be signed, with the reason
+    Rejected(String),
+    /// Wasn't signed in time and was dropped
+    Expired,
+}
+
+/// How far the federation's sessions progressed and what expired in them
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct GcStats {
+    /// The current session
+    pub session: u64,
+    /// Note requests that expired over the federation's lifetime, the count is
+    /// kept in consensus so it survives restarts
+    pub expired_notes: u64,
+}
+
+/// An event built by the federation that still needs to be threshold-signed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UnsignedEvent {
+    pub event: nostr_sdk::UnsignedEvent,
+}
+
+impl UnsignedEvent {
+    /// The NIP-01 event id the federation signs
+    pub fn message(&self) -> [u8; 32] {
+        self.event
+            .id
+            .as_bytes()
+            .try_into()
+            .expect("event ids are 32 bytes")
+    }
+
+    /// Attaches the signature created by the federation
+    pub fn add_signature(&self, sig: secp256k1::schnorr::Signature) -> anyhow::Result<Event> {
+        let sig = nostr_sdk::secp256k1::schnorr::Signature::from_slice(sig.as_ref())?;
+        Ok(Event {
+            event: self.event.clone().add_signature(sig)?,
+        })
+    }
+}
+
+impl Hash for UnsignedEvent {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.event.id.hash(state)
+    }
+}
+
+impl Decodable for UnsignedEvent {
+    fn consensus_decode<R: std::io::Read>(
+        r: &mut R,
+        modules: &fedimint_core::module::registry::ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let mut limited = std::io::Read::take(r, MAX_EVENT_SIZE as u64);
+        let event = decode_event_fields(&mut limited, modules)?;
+        Ok(UnsignedEvent { event })
+    }
+}
+
+impl Encodable for UnsignedEvent {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let event = &self.event;
+        encode_event_fields(
+            writer,
+            &event.id,
+            &event.pubkey,
+            event.created_at,
+            event.kind,
+            &event.tags,
+            &event.content,
+        )
+    }
+}
+
+/// Largest encoded event we decode, relays commonly reject bigger ones anyway
+pub const MAX_EVENT_SIZE: usize = 64 * 1024;
+
+/// Most tags we accept on a decoded event
+pub const MAX_EVENT_TAGS: usize = 1000;
+
+/// Encodes the fields of an event in NIP-01 order, so an event only ever has
+/// a single encoding no matter how its JSON was formatted
+fn encode_event_fields<W: std::io::Write>(
+    writer: &mut W,
+    id: &EventId,
+    pubkey: &nostr_sdk::secp256k1::XOnlyPublicKey,
+    created_at: Timestamp,
+    kind: Kind,
+    tags: &[Tag],
+    content: &str,
+) -> Result<usize, std::io::Error> {
+    writer.write_all(id.as_bytes())?;
+    writer.write_all(&pubkey.serialize())?;
+    let mut len = 64;
+    len += created_at.as_u64().consensus_encode(writer)?;
+    len += kind.as_u64().consensus_encode(writer)?;
+    let tags: Vec<Vec<String>> = tags.iter().map(Tag::as_vec).collect();
+    len += tags.consensus_encode(writer)?;
+    len += content.to_string().consensus_encode(writer)?;
+    Ok(len)
+}
+
+/// Decodes the fields of an event, checking it is within limits and its id
+/// commits to its content as NIP-01 requires
+fn decode_event_fields<R: std::io::Read>(
+    r: &mut R,
+    modules: &fedimint_core::module::registry::ModuleDecoderRegistry,
+) -> Result<nostr_sdk::UnsignedEvent, DecodeError> {
+    let id = EventId::from_slice(&read_array::<_, 32>(r)?).map_err(DecodeError::from_err)?;
+    let pubkey = nostr_sdk::secp256k1::XOnlyPublicKey::from_slice(&read_array::<_, 32>(r)?)
+        .map_err(DecodeError::from_err)?;
+    let created_at = Timestamp::from(u64::consensus_decode(r, modules)?);
+    let kind = Kind::from(u64::consensus_decode(r, modules)?);
+    let tags = Vec::<Vec<String>>::consensus_decode(r, modules)?;
+    let content = String::consensus_decode(r, modules)?;
+
+    if tags.len() > MAX_EVENT_TAGS {
+        return Err(DecodeError::from_str("Event has too many tags"));
     }
+    let tags = tags
+        .into_iter()
+        .map(Tag::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(DecodeError::from_err)?;
+    if id != EventId::new(&pubkey, created_at, &kind, &tags, &content) {
+        return Err(DecodeError::from_str("Event id doesn't match its content"));
+    }
+
+    Ok(nostr_sdk::UnsignedEvent {
+        id,
+        pubkey,
+        created_at,
+        kind,
+        tags,
+        content,
+    })
+}
+
+fn read_array<R: std::io::Read, const N: usize>(r: &mut R) -> Result<[u8; N], DecodeError> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes).map_err(DecodeError::from_err)?;
+    Ok(bytes)
 }
 
 /// Non-transaction items that will be submitted to consensus
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub enum NostimintConsensusItem {
-    /// User's message sign request signed by a single peer
-    Note(Event, SerdeSignatureShare), // Nonce here eventually
+    /// A peer's vote for the `created_at` of a note, the median of the
+    /// first `threshold` votes is the time the federation attests to
+    NoteRequest(NoteRequest, u64),
+    /// A peer's nonce commitment for signing a note, the first `threshold`
+    /// commitments received form the signing set
+    Nonce(UnsignedEvent, NonceCommitment),
+    /// A peer's signature share over a note once the signing set is complete
+    SignatureShare(UnsignedEvent, FrostSignatureShare),
+    /// A guardian's approval of a new federation profile with its vote for
+    /// the time, a threshold of approvals gets the profile signed
+    ProfileApproval(FederationProfile, u64),
+    /// A guardian's refusal to sign a note with its reason, instead of a vote
+    /// for its time
+    NoteVeto(NoteRequest, String),
+    /// A guardian's vote to end the given session, a threshold of votes starts
+    /// the next one and expires stale note requests
+    SessionVote(u64),
 }
 
 /// Input for a fedimint transaction
@@ -69,6 +428,11 @@ pub struct NostimintInput {
     pub amount: Amount,
     /// Associate the input with a user's pubkey
     pub account: XOnlyPublicKey,
+    /// A note to sign once the transaction is accepted, paying the note fee,
+    /// signed by the account that becomes its requester
+    pub note: Option<SignedNoteRequest>,
+    /// A NIP-05 name to register for the account, paying the name fee
+    pub name: Option<String>,
 }
 
 /// Output for a fedimint transaction
@@ -77,6 +441,9 @@ pub struct NostimintOutput {
     pub amount: Amount,
     /// Associate the output with a user's pubkey
     pub account: XOnlyPublicKey,
+    /// A NIP-57 zap request the output pays for, the federation signs a zap
+    /// receipt for it once the transaction is accepted
+    pub zap: Option<Event>,
 }
 
 /// Information needed by a client to update output funds
@@ -89,6 +456,22 @@ pub struct NostimintOutputOutcome(pub Amount, pub XOnlyPublicKey);
 pub enum NostimintError {
     #[error("Not enough funds")]
     NotEnoughFunds,
+    #[error("Invalid note request: {0}")]
+    InvalidNote(String),
+    #[error("Note was already requested")]
+    DuplicateNote,
+    #[error("Account already requested {0} notes in this window")]
+    QuotaExceeded(u64),
+    #[error("Invalid name: {0}")]
+    InvalidName(String),
+    #[error("Name is already registered")]
+    NameTaken,
+    #[error("Another account owns the federation's event of kind {0}")]
+    KindTaken(u64),
+    #[error("Invalid zap request: {0}")]
+    InvalidZap(String),
+    #[error("The federation doesn't print money")]
+    PrintingDisabled,
 }
 
 /// Contains the types defined above
@@ -147,13 +530,65 @@ impl fmt::Display for NostimintConsensusItem {
     }
 }
 
-/// A special key that creates assets for a test/example
-const FED_SECRET_PHRASE: &str = "Money printer go brrr...........";
+#[cfg(test)]
+mod tests {
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use nostr_sdk::{EventBuilder, Keys};
 
-pub fn fed_public_key() -> XOnlyPublicKey {
-    fed_key_pair().x_only_public_key().0
-}
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_events() -> anyhow::Result<()> {
+        let modules = ModuleDecoderRegistry::default();
+        let event = EventBuilder::new_text_note("hello", &[]).to_event(&Keys::generate())?;
+
+        let event = Event { event };
+        let mut bytes = vec![];
+        event.consensus_encode(&mut bytes)?;
+        let decoded = Event::consensus_decode(&mut bytes.as_slice(), &modules)?;
+        assert_eq!(decoded, event);
+
+        // The id no longer commits to the content
+        let at = bytes
+            .windows(5)
+            .position(|window| window == b"hello")
+            .expect("content is encoded");
+        bytes[at..at + 5].copy_from_slice(b"jello");
+        assert!(Event::consensus_decode(&mut bytes.as_slice(), &modules).is_err());
+
+        let mut bytes = vec![];
+        [0xff, 0xfe, 0xfd].as_slice().consensus_encode(&mut bytes)?;
+        assert!(Event::consensus_decode(&mut bytes.as_slice(), &modules).is_err());
 
-pub fn fed_key_pair() -> KeyPair {
-    KeyPair::from_seckey_slice(&Secp256k1::new(), FED_SECRET_PHRASE.as_bytes()).expect("32 bytes")
+        Ok(())
+    }
+
+    #[test]
+    fn verifies_signed_note_requests() {
+        let secp = Secp256k1::new();
+        let key = KeyPair::from_seckey_slice(&secp, &[1; 32]).expect("valid secret key");
+        let other = KeyPair::from_seckey_slice(&secp, &[2; 32]).expect("valid secret key");
+        let account = key.x_only_public_key().0;
+        let request = NoteRequest {
+            kind: kinds::TEXT_NOTE,
+            content: "hello".to_string(),
+            tags: vec![],
+            created_at: 1_700_000_000,
+        };
+
+        let signed = request.clone().sign(&key);
+        assert!(signed.verify(&account).is_ok());
+        assert!(signed.verify(&other.x_only_public_key().0).is_err());
+        assert!(request.clone().sign(&other).verify(&account).is_err());
+
+        // The signature covers every field of the request
+        let tampered = SignedNoteRequest {
+            request: NoteRequest {
+                content: "goodbye".to_string(),
+                ..request
+            },
+            ..signed
+        };
+        assert!(tampered.verify(&account).is_err());
+    }
 }
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
+use secp256k1::XOnlyPublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Longest name the federation registers
+pub const MAX_NAME_LEN: usize = 64;
+
+/// The `.well-known/nostr.json` document guardians serve, mapping the names
+/// registered with the federation to their accounts, NIP-05
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Nip05Document {
+    pub names: BTreeMap<String, XOnlyPublicKey>,
+}
+
+/// Checks a name is a valid NIP-05 local part, only lowercase names are
+/// registered so `Bob` and `bob` can't belong to different accounts
+pub fn validate_name(name: &str) -> anyhow::Result<()> {
+    ensure!(!name.is_empty(), "Names can't be empty");
+    ensure!(
+        name.len() <= MAX_NAME_LEN,
+        "Names can be at most {MAX_NAME_LEN} characters"
+    );
+    ensure!(
+        name.chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '-' | '_' | '.')),
+        "Names can only contain a-z, 0-9, `-`, `_` and `.`"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_names() {
+        for name in ["bob", "_", "alice.smith-1", &"a".repeat(MAX_NAME_LEN)] {
+            assert!(validate_name(name).is_ok(), "{name:?} is valid");
+        }
+        for name in [
+            "",
+            "Bob",
+            "bob@example.com",
+            "bob smith",
+            &"a".repeat(MAX_NAME_LEN + 1),
+        ] {
+            assert!(validate_name(name).is_err(), "{name:?} is invalid");
+        }
+    }
+}
@@ -1,14 +1,20 @@
+use std::hash::{Hash, Hasher};
+
 use fedimint_core::db::DatabaseTransaction;
-use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::epoch::SerdeSignatureShare;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::{impl_db_lookup, impl_db_record, Amount, OutPoint, PeerId};
-use fedimint_nostimint_common::Event;
+use fedimint_nostimint_common::frost::{FrostSignatureShare, NonceCommitment};
+use fedimint_nostimint_common::{
+    Event, FederationProfile, GcStats, NoteId, NoteRequest, NoteStatus, RelayReceipt, UnsignedEvent,
+};
 use futures::StreamExt;
-use nostr_sdk::EventId;
 use secp256k1::XOnlyPublicKey;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::publisher::DeliveryStatus;
 use crate::NostimintOutputOutcome;
 
 /// Namespaces DB keys for this module
@@ -19,6 +25,36 @@ pub enum DbKeyPrefix {
     Outcome = 0x02,
     SignatureShare = 0x03,
     Event = 0x04,
+    NonceCommitment = 0x05,
+    NoteRequest = 0x06,
+    Timestamp = 0x07,
+    Delivery = 0x08,
+    Receipt = 0x09,
+    ProfileApproval = 0x0b,
+    Profile = 0x0c,
+    Quota = 0x0d,
+    Requester = 0x0e,
+    Veto = 0x0f,
+    EventRequest = 0x10,
+    Session = 0x11,
+    SessionVote = 0x12,
+    Queued = 0x13,
+    GcStats = 0x14,
+    Name = 0x15,
+    RelayEvent = 0x16,
+    SigningRound = 0x17,
+    Stalled = 0x18,
+    Undelivered = 0x19,
+    SignedNote = 0x1a,
+    SignedCount = 0x1b,
+    PublisherCursor = 0x1c,
+    RelayTime = 0x1d,
+    RelayKind = 0x1e,
+    RelayAuthor = 0x1f,
+    RelayAuthorCount = 0x20,
+    RelayBytes = 0x21,
+    RelayCursor = 0x22,
+    KindOwner = 0x23,
 }
 
 // TODO: Boilerplate-code
@@ -83,6 +119,34 @@ pub async fn migrate_to_v1(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), any
     Ok(())
 }
 
+/// Events used to be encoded as their JSON, only kept to migrate old entries
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct JsonEventV0(pub nostr_sdk::Event);
+
+impl Hash for JsonEventV0 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.id.hash(state)
+    }
+}
+
+impl Encodable for JsonEventV0 {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        self.0.as_json().as_bytes().consensus_encode(writer)
+    }
+}
+
+impl Decodable for JsonEventV0 {
+    fn consensus_decode<R: std::io::Read>(
+        r: &mut R,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let bytes = Vec::<u8>::consensus_decode(r, modules)?;
+        let json = String::from_utf8(bytes).map_err(DecodeError::from_err)?;
+        let event = nostr_sdk::Event::from_json(json).map_err(DecodeError::from_err)?;
+        Ok(JsonEventV0(event))
+    }
+}
+
 /// Lookup tx outputs by key or prefix
 #[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
 pub struct NostimintOutcomeKey(pub OutPoint);
@@ -94,51 +158,721 @@ impl_db_record!(
     key = NostimintOutcomeKey,
     value = NostimintOutputOutcome,
     db_prefix = DbKeyPrefix::Outcome,
+    notify_on_modify = true
 );
 impl_db_lookup!(
     key = NostimintOutcomeKey,
     query_prefix = NostimintOutcomePrefix
 );
 
-/// Lookup signature requests by key or prefix
+/// Example old version 1 of DB entries, holding BLS signature shares
 #[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
-pub struct NostimintSignatureShareKey(pub Event, pub PeerId);
+pub struct NostimintSignatureShareKeyV1(pub JsonEventV0, pub PeerId);
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
-pub struct NostimintSignatureShareStringPrefix(pub EventId);
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintSignatureSharePrefixV1;
 
-impl Encodable for NostimintSignatureShareStringPrefix {
-    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
-        self.0.as_bytes().consensus_encode(writer)
-    }
-}
+impl_db_record!(
+    key = NostimintSignatureShareKeyV1,
+    value = SerdeSignatureShare,
+    db_prefix = DbKeyPrefix::SignatureShare,
+);
+impl_db_lookup!(
+    key = NostimintSignatureShareKeyV1,
+    query_prefix = NostimintSignatureSharePrefixV1
+);
+
+/// Lookup signature shares by key, all of a note's shares by its id
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintSignatureShareKey(pub NoteId, pub PeerId);
 
 #[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
 pub struct NostimintSignatureSharePrefix;
 
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintSignatureShareNotePrefix(pub NoteId);
+
 impl_db_record!(
     key = NostimintSignatureShareKey,
-    value = SerdeSignatureShare,
+    value = FrostSignatureShare,
     db_prefix = DbKeyPrefix::SignatureShare,
 );
 impl_db_lookup!(
     key = NostimintSignatureShareKey,
-    query_prefix = NostimintSignatureShareStringPrefix,
-    query_prefix = NostimintSignatureSharePrefix
+    query_prefix = NostimintSignatureSharePrefix,
+    query_prefix = NostimintSignatureShareNotePrefix
+);
+
+/// Lookup nonce commitments by key, a note's signing set by its id
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintNonceKey(pub NoteId, pub PeerId);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintNoncePrefix;
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintNonceNotePrefix(pub NoteId);
+
+impl_db_record!(
+    key = NostimintNonceKey,
+    value = NonceCommitment,
+    db_prefix = DbKeyPrefix::NonceCommitment,
 );
+impl_db_lookup!(
+    key = NostimintNonceKey,
+    query_prefix = NostimintNoncePrefix,
+    query_prefix = NostimintNonceNotePrefix
+);
+
+/// Example old version 1 of signature requests, keyed by the event's JSON
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintEventKeyV1(pub JsonEventV0);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintEventPrefixV1;
+
+impl_db_record!(
+    key = NostimintEventKeyV1,
+    value = Option<JsonEventV0>,
+    db_prefix = DbKeyPrefix::Event,
+);
+impl_db_lookup!(
+    key = NostimintEventKeyV1,
+    query_prefix = NostimintEventPrefixV1
+);
+
+/// BLS signature shares can't be turned into FROST shares, and notes used to be
+/// built by clients, which could pick any pubkey or id, so notes still being
+/// signed are dropped and have to be requested again
+pub async fn migrate_to_v2(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow::Error> {
+    dbtx.remove_by_prefix(&NostimintSignatureSharePrefixV1)
+        .await;
+    dbtx.remove_by_prefix(&NostimintEventPrefixV1).await;
+    Ok(())
+}
 
 /// Lookup signature requests by key or prefix
 #[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
-pub struct NostimintKind1Key(pub Event);
+pub struct NostimintEventKey(pub UnsignedEvent);
 
 #[derive(Debug, Encodable, Decodable)]
-pub struct NostimintKind1Prefix;
+pub struct NostimintEventPrefix;
 
 impl_db_record!(
-    key = NostimintKind1Key,
+    key = NostimintEventKey,
     value = Option<Event>,
     db_prefix = DbKeyPrefix::Event,
     // Allows us to listen for notifications on this key
     notify_on_modify = true
 );
-impl_db_lookup!(key = NostimintKind1Key, query_prefix = NostimintKind1Prefix);
+impl_db_lookup!(key = NostimintEventKey, query_prefix = NostimintEventPrefix);
+
+/// Lookup the status of a note request by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintNoteRequestKey(pub NoteRequest);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintNoteRequestPrefix;
+
+impl_db_record!(
+    key = NostimintNoteRequestKey,
+    value = NoteStatus,
+    db_prefix = DbKeyPrefix::NoteRequest,
+    notify_on_modify = true
+);
+impl_db_lookup!(
+    key = NostimintNoteRequestKey,
+    query_prefix = NostimintNoteRequestPrefix
+);
+
+/// Lookup each peer's vote for the time of a note by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintTimestampKey(pub NoteRequest, pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintTimestampPrefix;
+
+impl_db_record!(
+    key = NostimintTimestampKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::Timestamp,
+);
+impl_db_lookup!(
+    key = NostimintTimestampKey,
+    query_prefix = NostimintTimestampPrefix
+);
+
+/// Lookup the attempts to deliver a signed note to a relay by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintDeliveryKey(pub NoteId, pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintDeliveryPrefix;
+
+impl_db_record!(
+    key = NostimintDeliveryKey,
+    value = DeliveryStatus,
+    db_prefix = DbKeyPrefix::Delivery,
+);
+impl_db_lookup!(
+    key = NostimintDeliveryKey,
+    query_prefix = NostimintDeliveryPrefix
+);
+
+/// Lookup the answers of relays to a signed note by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintReceiptKey(pub NoteId, pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintReceiptNotePrefix(pub NoteId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintReceiptPrefix;
+
+impl_db_record!(
+    key = NostimintReceiptKey,
+    value = RelayReceipt,
+    db_prefix = DbKeyPrefix::Receipt,
+);
+impl_db_lookup!(
+    key = NostimintReceiptKey,
+    query_prefix = NostimintReceiptPrefix,
+    query_prefix = NostimintReceiptNotePrefix
+);
+
+/// Lookup each guardian's approval of a profile and its time vote by key, all
+/// approvals of a profile by the profile
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintProfileApprovalKey(pub FederationProfile, pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintProfileApprovalPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintProfileApprovalProfilePrefix(pub FederationProfile);
+
+impl_db_record!(
+    key = NostimintProfileApprovalKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ProfileApproval,
+);
+impl_db_lookup!(
+    key = NostimintProfileApprovalKey,
+    query_prefix = NostimintProfileApprovalPrefix,
+    query_prefix = NostimintProfileApprovalProfilePrefix
+);
+
+/// The latest signed federation profile
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintProfileKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintProfilePrefix;
+
+impl_db_record!(
+    key = NostimintProfileKey,
+    value = Event,
+    db_prefix = DbKeyPrefix::Profile,
+);
+impl_db_lookup!(
+    key = NostimintProfileKey,
+    query_prefix = NostimintProfilePrefix
+);
+
+/// Lookup how many notes an account requested in a quota window by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintQuotaKey(pub XOnlyPublicKey, pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintQuotaAccountPrefix(pub XOnlyPublicKey);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintQuotaPrefix;
+
+impl_db_record!(
+    key = NostimintQuotaKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::Quota,
+);
+impl_db_lookup!(
+    key = NostimintQuotaKey,
+    query_prefix = NostimintQuotaPrefix,
+    query_prefix = NostimintQuotaAccountPrefix
+);
+
+/// Lookup the account that paid for a note request by key or prefix, it is
+/// authenticated by the account's signature over the paying tx
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintRequesterKey(pub NoteRequest);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRequesterPrefix;
+
+impl_db_record!(
+    key = NostimintRequesterKey,
+    value = XOnlyPublicKey,
+    db_prefix = DbKeyPrefix::Requester,
+);
+impl_db_lookup!(
+    key = NostimintRequesterKey,
+    query_prefix = NostimintRequesterPrefix
+);
+
+/// Lookup the guardians that refused to sign a note request by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintVetoKey(pub NoteRequest, pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintVetoPrefix;
+
+impl_db_record!(
+    key = NostimintVetoKey,
+    value = String,
+    db_prefix = DbKeyPrefix::Veto,
+);
+impl_db_lookup!(key = NostimintVetoKey, query_prefix = NostimintVetoPrefix);
+
+/// Lookup the note request an event was built from by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintEventRequestKey(pub NoteId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintEventRequestPrefix;
+
+impl_db_record!(
+    key = NostimintEventRequestKey,
+    value = NoteRequest,
+    db_prefix = DbKeyPrefix::EventRequest,
+);
+impl_db_lookup!(
+    key = NostimintEventRequestKey,
+    query_prefix = NostimintEventRequestPrefix
+);
+
+/// The session the federation is in
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintSessionKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintSessionPrefix;
+
+impl_db_record!(
+    key = NostimintSessionKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::Session,
+);
+impl_db_lookup!(
+    key = NostimintSessionKey,
+    query_prefix = NostimintSessionPrefix
+);
+
+/// Lookup the guardians that voted to end a session by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintSessionVoteKey(pub u64, pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintSessionVotePrefix;
+
+impl_db_record!(
+    key = NostimintSessionVoteKey,
+    value = (),
+    db_prefix = DbKeyPrefix::SessionVote,
+);
+impl_db_lookup!(
+    key = NostimintSessionVoteKey,
+    query_prefix = NostimintSessionVotePrefix
+);
+
+/// Lookup the session a note request was queued in by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintQueuedKey(pub NoteRequest);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintQueuedPrefix;
+
+impl_db_record!(
+    key = NostimintQueuedKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::Queued,
+);
+impl_db_lookup!(
+    key = NostimintQueuedKey,
+    query_prefix = NostimintQueuedPrefix
+);
+
+/// Counts of what the garbage collection of sessions pruned
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintGcStatsKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintGcStatsPrefix;
+
+impl_db_record!(
+    key = NostimintGcStatsKey,
+    value = GcStats,
+    db_prefix = DbKeyPrefix::GcStats,
+);
+impl_db_lookup!(
+    key = NostimintGcStatsKey,
+    query_prefix = NostimintGcStatsPrefix
+);
+
+/// Lookup the account a NIP-05 name is registered to by name or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintNameKey(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintNamePrefix;
+
+impl_db_record!(
+    key = NostimintNameKey,
+    value = XOnlyPublicKey,
+    db_prefix = DbKeyPrefix::Name,
+);
+impl_db_lookup!(key = NostimintNameKey, query_prefix = NostimintNamePrefix);
+
+/// Events our embedded relay serves by their id or prefix, signed notes and
+/// what accounts published to it, only our guardian serves them so they aren't
+/// part of consensus
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintRelayEventKey(pub NoteId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayEventPrefix;
+
+impl_db_record!(
+    key = NostimintRelayEventKey,
+    value = Event,
+    db_prefix = DbKeyPrefix::RelayEvent,
+);
+impl_db_lookup!(
+    key = NostimintRelayEventKey,
+    query_prefix = NostimintRelayEventPrefix
+);
+
+/// Lookup the events our relay serves newest first by prefix, keyed by how
+/// long before the end of time they were created
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintRelayTimeKey(pub u64, pub NoteId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayTimePrefix;
+
+impl_db_record!(
+    key = NostimintRelayTimeKey,
+    value = (),
+    db_prefix = DbKeyPrefix::RelayTime,
+);
+impl_db_lookup!(
+    key = NostimintRelayTimeKey,
+    query_prefix = NostimintRelayTimePrefix
+);
+
+/// Lookup the events of a kind our relay serves newest first by prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintRelayKindKey(pub u64, pub u64, pub NoteId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayKindPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayKindEventsPrefix(pub u64);
+
+impl_db_record!(
+    key = NostimintRelayKindKey,
+    value = (),
+    db_prefix = DbKeyPrefix::RelayKind,
+);
+impl_db_lookup!(
+    key = NostimintRelayKindKey,
+    query_prefix = NostimintRelayKindPrefix,
+    query_prefix = NostimintRelayKindEventsPrefix
+);
+
+/// Lookup the events of an author our relay serves newest first by prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintRelayAuthorKey(pub XOnlyPublicKey, pub u64, pub NoteId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayAuthorPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayAuthorEventsPrefix(pub XOnlyPublicKey);
+
+impl_db_record!(
+    key = NostimintRelayAuthorKey,
+    value = (),
+    db_prefix = DbKeyPrefix::RelayAuthor,
+);
+impl_db_lookup!(
+    key = NostimintRelayAuthorKey,
+    query_prefix = NostimintRelayAuthorPrefix,
+    query_prefix = NostimintRelayAuthorEventsPrefix
+);
+
+/// Lookup how many events an account published to our relay by key or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintRelayAuthorCountKey(pub XOnlyPublicKey);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayAuthorCountPrefix;
+
+impl_db_record!(
+    key = NostimintRelayAuthorCountKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::RelayAuthorCount,
+);
+impl_db_lookup!(
+    key = NostimintRelayAuthorCountKey,
+    query_prefix = NostimintRelayAuthorCountPrefix
+);
+
+/// How many bytes of events accounts published to our relay in total
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintRelayBytesKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayBytesPrefix;
+
+impl_db_record!(
+    key = NostimintRelayBytesKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::RelayBytes,
+);
+impl_db_lookup!(
+    key = NostimintRelayBytesKey,
+    query_prefix = NostimintRelayBytesPrefix
+);
+
+/// How many signed notes our relay already indexed
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintRelayCursorKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintRelayCursorPrefix;
+
+impl_db_record!(
+    key = NostimintRelayCursorKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::RelayCursor,
+);
+impl_db_lookup!(
+    key = NostimintRelayCursorKey,
+    query_prefix = NostimintRelayCursorPrefix
+);
+
+/// Lookup the session the current signing set of a note started in by its id
+/// or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintSigningRoundKey(pub NoteId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintSigningRoundPrefix;
+
+impl_db_record!(
+    key = NostimintSigningRoundKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::SigningRound,
+);
+impl_db_lookup!(
+    key = NostimintSigningRoundKey,
+    query_prefix = NostimintSigningRoundPrefix
+);
+
+/// Lookup the peers excluded from the signing sets of a note by key, all of a
+/// note's by its id, because they vetoed it or left a set without signing
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintStalledKey(pub NoteId, pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintStalledPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintStalledNotePrefix(pub NoteId);
+
+impl_db_record!(
+    key = NostimintStalledKey,
+    value = (),
+    db_prefix = DbKeyPrefix::Stalled,
+);
+impl_db_lookup!(
+    key = NostimintStalledKey,
+    query_prefix = NostimintStalledPrefix,
+    query_prefix = NostimintStalledNotePrefix
+);
+
+/// Lookup signed notes our publisher still has to deliver to some relay by
+/// their id or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintUndeliveredKey(pub NoteId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintUndeliveredPrefix;
+
+impl_db_record!(
+    key = NostimintUndeliveredKey,
+    value = Event,
+    db_prefix = DbKeyPrefix::Undelivered,
+);
+impl_db_lookup!(
+    key = NostimintUndeliveredKey,
+    query_prefix = NostimintUndeliveredPrefix
+);
+
+/// Lookup the notes the federation signed by the order they were signed in or
+/// prefix, background tasks follow them once they are committed
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintSignedNoteKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintSignedNotePrefix;
+
+impl_db_record!(
+    key = NostimintSignedNoteKey,
+    value = Event,
+    db_prefix = DbKeyPrefix::SignedNote,
+    notify_on_modify = true
+);
+impl_db_lookup!(
+    key = NostimintSignedNoteKey,
+    query_prefix = NostimintSignedNotePrefix
+);
+
+/// How many notes the federation signed so far
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintSignedCountKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintSignedCountPrefix;
+
+impl_db_record!(
+    key = NostimintSignedCountKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::SignedCount,
+);
+impl_db_lookup!(
+    key = NostimintSignedCountKey,
+    query_prefix = NostimintSignedCountPrefix
+);
+
+/// How many signed notes our publisher already queued for delivery
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintPublisherCursorKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintPublisherCursorPrefix;
+
+impl_db_record!(
+    key = NostimintPublisherCursorKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::PublisherCursor,
+);
+impl_db_lookup!(
+    key = NostimintPublisherCursorKey,
+    query_prefix = NostimintPublisherCursorPrefix
+);
+
+/// Lookup the account that owns the federation's event of a kind relays only
+/// keep one of by kind or prefix
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct NostimintKindOwnerKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NostimintKindOwnerPrefix;
+
+impl_db_record!(
+    key = NostimintKindOwnerKey,
+    value = XOnlyPublicKey,
+    db_prefix = DbKeyPrefix::KindOwner,
+);
+impl_db_lookup!(
+    key = NostimintKindOwnerKey,
+    query_prefix = NostimintKindOwnerPrefix
+);
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::Keys;
+
+    use super::*;
+    use crate::tests::{memory_db, single_guardian};
+
+    /// An event as the federation would build it for a text note
+    fn unsigned_note(content: &str) -> anyhow::Result<UnsignedEvent> {
+        let request = NoteRequest {
+            kind: 1,
+            content: content.to_string(),
+            tags: vec![],
+            created_at: 1_700_000_000,
+        };
+        request.to_unsigned_event(Keys::generate().public_key(), request.created_at)
+    }
+
+    #[tokio::test]
+    async fn collects_signature_shares_per_note() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let note = unsigned_note("first")?;
+        let other = unsigned_note("second")?;
+        let id = NoteId(note.event.id);
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            for peer in 0..3 {
+                let key = NostimintSignatureShareKey(id, PeerId::from(peer));
+                dbtx.insert_new_entry(&key, &FrostSignatureShare([peer as u8; 32]))
+                    .await;
+            }
+            let other_key = NostimintSignatureShareKey(NoteId(other.event.id), PeerId::from(0));
+            dbtx.insert_new_entry(&other_key, &FrostSignatureShare([9; 32]))
+                .await;
+
+            let shares = module.signature_shares(&mut dbtx, &note).await;
+            assert_eq!(
+                shares.keys().copied().collect::<Vec<_>>(),
+                (0..3).map(PeerId::from).collect::<Vec<_>>()
+            );
+            assert_eq!(shares[&PeerId::from(2)], FrostSignatureShare([2; 32]));
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn migrates_from_baseline_layout() -> anyhow::Result<()> {
+        let db = memory_db();
+        let keys = Keys::generate();
+        let account = XOnlyPublicKey::from_slice(&keys.public_key().serialize())?;
+        let pending = nostr_sdk::EventBuilder::new_text_note("pending", &[]).to_event(&keys)?;
+        let signed = nostr_sdk::EventBuilder::new_text_note("signed", &[]).to_event(&keys)?;
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(&NostimintFundsKeyV0(account), &())
+            .await;
+        dbtx.insert_new_entry(&NostimintEventKeyV1(JsonEventV0(pending)), &None)
+            .await;
+        dbtx.insert_new_entry(
+            &NostimintEventKeyV1(JsonEventV0(signed.clone())),
+            &Some(JsonEventV0(signed)),
+        )
+        .await;
+        migrate_to_v1(&mut dbtx).await?;
+        migrate_to_v2(&mut dbtx).await?;
+
+        assert_eq!(
+            dbtx.get_value(&NostimintFundsKeyV1(account)).await,
+            Some(Amount::ZERO)
+        );
+        let events = dbtx
+            .find_by_prefix(&NostimintEventPrefix)
+            .await
+            .count()
+            .await;
+        assert_eq!(events, 0);
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+}
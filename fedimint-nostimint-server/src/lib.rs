@@ -1,52 +1,105 @@
-use std::collections::BTreeMap;
-use std::env;
+use std::collections::{BTreeMap, BTreeSet};
 use std::string::ToString;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use nostr_sdk::prelude::FromSkStr;
-use nostr_sdk::{EventId, Keys, ToBech32};
+use nostr_sdk::{EventId, Kind};
 
 use anyhow::bail;
 use async_trait::async_trait;
 use fedimint_core::config::{
-    ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
+    ConfigGenModuleParams, DkgError, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
 use fedimint_core::db::{Database, DatabaseVersion, MigrationMap, ModuleDatabaseTransaction};
-use fedimint_core::epoch::SerdeSignatureShare;
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::{
-    api_endpoint, ApiEndpoint, ConsensusProposal, CoreConsensusVersion, ExtendsCommonModuleInit,
-    InputMeta, IntoModuleError, ModuleConsensusVersion, ModuleError, PeerHandle, ServerModuleInit,
-    SupportedModuleApiVersions, TransactionItemAmount,
+    api_endpoint, ApiEndpoint, ApiError, ConsensusProposal, CoreConsensusVersion,
+    ExtendsCommonModuleInit, InputMeta, IntoModuleError, ModuleConsensusVersion, ModuleError,
+    PeerHandle, ServerModuleInit, SupportedModuleApiVersions, TransactionItemAmount,
 };
 use fedimint_core::server::DynServerModule;
-use fedimint_core::task::TaskGroup;
+use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::{push_db_pair_items, Amount, OutPoint, PeerId, ServerModule};
 pub use fedimint_nostimint_common::config::{
     NostimintClientConfig, NostimintConfig, NostimintConfigConsensus, NostimintConfigLocal,
     NostimintConfigPrivate, NostimintGenParams,
 };
-use fedimint_nostimint_common::Event;
+use fedimint_nostimint_common::frost::{
+    self, DkgDealer, FrostSignatureShare, NonceCommitment, SecretNonce,
+};
+use fedimint_nostimint_common::kinds;
+use fedimint_nostimint_common::nip05::{self, Nip05Document};
+use fedimint_nostimint_common::{
+    Event, FederationProfile, GcStats, NoteId, NoteRequest, NoteStatus, RelayReceipt, UnsignedEvent,
+};
 pub use fedimint_nostimint_common::{
-    fed_public_key, NostimintCommonGen, NostimintConsensusItem, NostimintError, NostimintInput,
+    NostimintCommonGen, NostimintConsensusItem, NostimintError, NostimintInput,
     NostimintModuleTypes, NostimintOutput, NostimintOutputOutcome, CONSENSUS_VERSION, KIND,
 };
 use fedimint_server::config::distributedgen::PeerHandleOps;
 use futures::{FutureExt, StreamExt};
+use secp256k1::{PublicKey, Secp256k1, XOnlyPublicKey};
 use strum::IntoEnumIterator;
+use tokio::net::TcpListener;
 use tokio::sync::Notify;
+use tracing::{debug, warn};
 
 use crate::db::{
-    migrate_to_v1, DbKeyPrefix, NostimintFundsKeyV1, NostimintFundsPrefixV1, NostimintKind1Key,
-    NostimintKind1Prefix, NostimintOutcomeKey, NostimintOutcomePrefix, NostimintSignatureShareKey,
-    NostimintSignatureSharePrefix, NostimintSignatureShareStringPrefix,
+    migrate_to_v1, migrate_to_v2, DbKeyPrefix, NostimintDeliveryKey, NostimintDeliveryPrefix,
+    NostimintEventKey, NostimintEventPrefix, NostimintEventRequestKey, NostimintEventRequestPrefix,
+    NostimintFundsKeyV1, NostimintFundsPrefixV1, NostimintGcStatsKey, NostimintGcStatsPrefix,
+    NostimintKindOwnerKey, NostimintKindOwnerPrefix, NostimintNameKey, NostimintNamePrefix,
+    NostimintNonceKey, NostimintNonceNotePrefix, NostimintNoncePrefix, NostimintNoteRequestKey,
+    NostimintNoteRequestPrefix, NostimintOutcomeKey, NostimintOutcomePrefix,
+    NostimintProfileApprovalKey, NostimintProfileApprovalPrefix,
+    NostimintProfileApprovalProfilePrefix, NostimintProfileKey, NostimintProfilePrefix,
+    NostimintPublisherCursorKey, NostimintPublisherCursorPrefix, NostimintQueuedKey,
+    NostimintQueuedPrefix, NostimintQuotaAccountPrefix, NostimintQuotaKey, NostimintQuotaPrefix,
+    NostimintReceiptKey, NostimintReceiptNotePrefix, NostimintReceiptPrefix,
+    NostimintRelayAuthorCountKey, NostimintRelayAuthorCountPrefix, NostimintRelayAuthorKey,
+    NostimintRelayAuthorPrefix, NostimintRelayBytesKey, NostimintRelayBytesPrefix,
+    NostimintRelayCursorKey, NostimintRelayCursorPrefix, NostimintRelayEventKey,
+    NostimintRelayEventPrefix, NostimintRelayKindKey, NostimintRelayKindPrefix,
+    NostimintRelayTimeKey, NostimintRelayTimePrefix, NostimintRequesterKey,
+    NostimintRequesterPrefix, NostimintSessionKey, NostimintSessionPrefix, NostimintSessionVoteKey,
+    NostimintSessionVotePrefix, NostimintSignatureShareKey, NostimintSignatureShareNotePrefix,
+    NostimintSignatureSharePrefix, NostimintSignedCountKey, NostimintSignedCountPrefix,
+    NostimintSignedNoteKey, NostimintSignedNotePrefix, NostimintSigningRoundKey,
+    NostimintSigningRoundPrefix, NostimintStalledKey, NostimintStalledNotePrefix,
+    NostimintStalledPrefix, NostimintTimestampKey, NostimintTimestampPrefix,
+    NostimintUndeliveredKey, NostimintUndeliveredPrefix, NostimintVetoKey, NostimintVetoPrefix,
 };
+use crate::policy::NotePolicy;
+use crate::publisher::{DeliveryStatus, RelayPublisher};
+use crate::relay::EmbeddedRelay;
 
 mod db;
 
+// Delivers signed notes to nostr relays
+pub mod publisher;
+
+// Lets guardians refuse to sign notes
+pub mod policy;
+
+// Serves the federation's notes over a relay embedded in each guardian
+pub mod relay;
+
 /// Generates the module
-#[derive(Debug, Clone)]
-pub struct NostimintGen;
+#[derive(Debug, Clone, Default)]
+pub struct NostimintGen {
+    /// Replaces the policy of the local config
+    policy: Option<Arc<dyn NotePolicy>>,
+}
+
+impl NostimintGen {
+    /// Vetoes notes with a custom policy instead of the one in the local config
+    pub fn with_policy(policy: Arc<dyn NotePolicy>) -> Self {
+        Self {
+            policy: Some(policy),
+        }
+    }
+}
 
 // TODO: Boilerplate-code
 impl ExtendsCommonModuleInit for NostimintGen {
@@ -57,7 +110,7 @@ impl ExtendsCommonModuleInit for NostimintGen {
 #[async_trait]
 impl ServerModuleInit for NostimintGen {
     type Params = NostimintGenParams;
-    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(2);
 
     /// Returns the version of this module
     fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
@@ -72,16 +125,54 @@ impl ServerModuleInit for NostimintGen {
     async fn init(
         &self,
         cfg: ServerModuleConfig,
-        _db: Database,
-        _task_group: &mut TaskGroup,
+        db: Database,
+        task_group: &mut TaskGroup,
     ) -> anyhow::Result<DynServerModule> {
-        Ok(Nostimint::new(cfg.to_typed()?).into())
+        let cfg: NostimintConfig = cfg.to_typed()?;
+        let policy = match &self.policy {
+            Some(policy) => policy.clone(),
+            None => Arc::new(cfg.local.policy.clone()),
+        };
+        let module = Nostimint::new(cfg, policy)?;
+
+        // Serve signed notes and our accounts' events on our own relay
+        if let Some(relay_bind) = &module.cfg.local.relay_bind {
+            let listener = TcpListener::bind(relay_bind).await?;
+            let relay = EmbeddedRelay::new(db.clone(), module.cfg.consensus.issuer_key);
+            let indexer = relay.clone();
+            task_group
+                .spawn("nostimint-relay-index", move |handle| {
+                    indexer.index_signed_notes(handle)
+                })
+                .await;
+            task_group
+                .spawn("nostimint-embedded-relay", move |handle| {
+                    relay.run(listener, handle)
+                })
+                .await;
+        }
+
+        // Push signed notes to the relays this guardian is configured with
+        let publisher = RelayPublisher {
+            db,
+            our_peer_id: module.our_peer_id,
+            relays: module.cfg.local.relays.clone(),
+            max_attempts: module.cfg.local.publish_attempts,
+        };
+        task_group
+            .spawn("nostimint-relay-publisher", move |handle| {
+                publisher.run(handle)
+            })
+            .await;
+
+        Ok(module.into())
     }
 
     /// DB migrations to move from old to newer versions
     fn get_database_migrations(&self) -> MigrationMap {
         let mut migrations = MigrationMap::new();
         migrations.insert(DatabaseVersion(0), move |dbtx| migrate_to_v1(dbtx).boxed());
+        migrations.insert(DatabaseVersion(1), move |dbtx| migrate_to_v2(dbtx).boxed());
         migrations
     }
 
@@ -128,16 +219,53 @@ impl ServerModuleInit for NostimintGen {
         let g1 = peers.run_dkg_g1(()).await?;
         let keys = g1[&()].threshold_crypto();
 
+        // Runs a second DKG for the federation's nostr key, with the same threshold
+        let threshold = keys.public_key_set.threshold() + 1;
+        let dealer = DkgDealer::new(peers.our_id, threshold);
+        let commitments = peers
+            .exchange_encodable(
+                "nostimint-frost-commitments".to_string(),
+                dealer.commitment(),
+            )
+            .await?;
+        let dealt_shares = peers
+            .exchange_encodable(
+                "nostimint-frost-shares".to_string(),
+                dealer.encrypted_shares(&commitments),
+            )
+            .await?;
+        let our_shares = dealt_shares
+            .into_iter()
+            .filter_map(|(peer, mut shares)| Some((peer, shares.remove(&peers.our_id)?)))
+            .collect();
+        let (frost_key, frost_key_share) = dealer
+            .finish(&commitments, &our_shares)
+            .map_err(DkgError::Failed)?;
+
         Ok(NostimintConfig {
             local: NostimintConfigLocal {
-                example: params.local.0.clone(),
+                example: params.local.example.clone(),
+                relays: params.local.relays.clone(),
+                publish_attempts: params.local.publish_attempts,
+                policy: params.local.policy.clone(),
+                relay_bind: params.local.relay_bind.clone(),
             },
             private: NostimintConfigPrivate {
                 private_key_share: keys.secret_key_share,
+                frost_key_share,
             },
             consensus: NostimintConfigConsensus {
                 public_key_set: keys.public_key_set,
+                frost_key,
                 tx_fee: params.consensus.tx_fee,
+                note_fee: params.consensus.note_fee,
+                name_fee: params.consensus.name_fee,
+                timestamp_tolerance: params.consensus.timestamp_tolerance,
+                allowed_kinds: params.consensus.allowed_kinds.clone(),
+                note_quota: params.consensus.note_quota,
+                session_length: params.consensus.session_length,
+                expiry_sessions: params.consensus.expiry_sessions,
+                issuer_key: params.consensus.issuer_key,
             },
         }
         .to_erased())
@@ -151,7 +279,11 @@ impl ServerModuleInit for NostimintGen {
         let config = NostimintConfigConsensus::from_erased(config)?;
         Ok(NostimintClientConfig {
             tx_fee: config.tx_fee,
+            note_fee: config.note_fee,
+            name_fee: config.name_fee,
             fed_public_key: config.public_key_set.public_key(),
+            nostr_public_key: config.frost_key.group_key,
+            issuer_key: config.issuer_key,
         })
     }
 
@@ -165,6 +297,13 @@ impl ServerModuleInit for NostimintGen {
         if config.private.private_key_share.public_key_share() != our_share {
             bail!("Private key doesn't match public key share");
         }
+
+        // Check our share of the nostr key matches its public key share
+        let our_frost_share =
+            PublicKey::from_secret_key(&Secp256k1::new(), &config.private.frost_key_share);
+        if config.consensus.frost_key.public_shares.get(identity) != Some(&our_frost_share) {
+            bail!("Private nostr key share doesn't match public key share");
+        }
         Ok(())
     }
 
@@ -207,7 +346,7 @@ impl ServerModuleInit for NostimintGen {
                         dbtx,
                         NostimintSignatureSharePrefix,
                         NostimintSignatureShareKey,
-                        SerdeSignatureShare,
+                        FrostSignatureShare,
                         items,
                         "Nostimint Signature Shares"
                     );
@@ -215,13 +354,313 @@ impl ServerModuleInit for NostimintGen {
                 DbKeyPrefix::Event => {
                     push_db_pair_items!(
                         dbtx,
-                        NostimintKind1Prefix,
-                        NostimintSignatureKey,
+                        NostimintEventPrefix,
+                        NostimintEventKey,
                         Option<Event>,
                         items,
                         "Nostimint Events"
                     );
                 }
+                DbKeyPrefix::Timestamp => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintTimestampPrefix,
+                        NostimintTimestampKey,
+                        u64,
+                        items,
+                        "Nostimint Timestamp Votes"
+                    );
+                }
+                DbKeyPrefix::NoteRequest => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintNoteRequestPrefix,
+                        NostimintNoteRequestKey,
+                        NoteStatus,
+                        items,
+                        "Nostimint Note Requests"
+                    );
+                }
+                DbKeyPrefix::EventRequest => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintEventRequestPrefix,
+                        NostimintEventRequestKey,
+                        NoteRequest,
+                        items,
+                        "Nostimint Event Requests"
+                    );
+                }
+                DbKeyPrefix::Delivery => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintDeliveryPrefix,
+                        NostimintDeliveryKey,
+                        DeliveryStatus,
+                        items,
+                        "Nostimint Relay Deliveries"
+                    );
+                }
+                DbKeyPrefix::Receipt => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintReceiptPrefix,
+                        NostimintReceiptKey,
+                        RelayReceipt,
+                        items,
+                        "Nostimint Relay Receipts"
+                    );
+                }
+                DbKeyPrefix::ProfileApproval => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintProfileApprovalPrefix,
+                        NostimintProfileApprovalKey,
+                        u64,
+                        items,
+                        "Nostimint Profile Approvals"
+                    );
+                }
+                DbKeyPrefix::Profile => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintProfilePrefix,
+                        NostimintProfileKey,
+                        Event,
+                        items,
+                        "Nostimint Profile"
+                    );
+                }
+                DbKeyPrefix::Quota => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintQuotaPrefix,
+                        NostimintQuotaKey,
+                        u64,
+                        items,
+                        "Nostimint Note Quotas"
+                    );
+                }
+                DbKeyPrefix::Requester => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintRequesterPrefix,
+                        NostimintRequesterKey,
+                        XOnlyPublicKey,
+                        items,
+                        "Nostimint Note Requesters"
+                    );
+                }
+                DbKeyPrefix::Veto => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintVetoPrefix,
+                        NostimintVetoKey,
+                        String,
+                        items,
+                        "Nostimint Note Vetoes"
+                    );
+                }
+                DbKeyPrefix::Session => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintSessionPrefix,
+                        NostimintSessionKey,
+                        u64,
+                        items,
+                        "Nostimint Session"
+                    );
+                }
+                DbKeyPrefix::SessionVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintSessionVotePrefix,
+                        NostimintSessionVoteKey,
+                        (),
+                        items,
+                        "Nostimint Session Votes"
+                    );
+                }
+                DbKeyPrefix::Queued => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintQueuedPrefix,
+                        NostimintQueuedKey,
+                        u64,
+                        items,
+                        "Nostimint Queued Note Requests"
+                    );
+                }
+                DbKeyPrefix::GcStats => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintGcStatsPrefix,
+                        NostimintGcStatsKey,
+                        GcStats,
+                        items,
+                        "Nostimint GC Stats"
+                    );
+                }
+                DbKeyPrefix::RelayEvent => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintRelayEventPrefix,
+                        NostimintRelayEventKey,
+                        Event,
+                        items,
+                        "Nostimint Relay Events"
+                    );
+                }
+                DbKeyPrefix::Name => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintNamePrefix,
+                        NostimintNameKey,
+                        XOnlyPublicKey,
+                        items,
+                        "Nostimint Names"
+                    );
+                }
+                DbKeyPrefix::NonceCommitment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintNoncePrefix,
+                        NostimintNonceKey,
+                        NonceCommitment,
+                        items,
+                        "Nostimint Nonce Commitments"
+                    );
+                }
+                DbKeyPrefix::SigningRound => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintSigningRoundPrefix,
+                        NostimintSigningRoundKey,
+                        u64,
+                        items,
+                        "Nostimint Signing Rounds"
+                    );
+                }
+                DbKeyPrefix::Stalled => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintStalledPrefix,
+                        NostimintStalledKey,
+                        (),
+                        items,
+                        "Nostimint Excluded Signers"
+                    );
+                }
+                DbKeyPrefix::Undelivered => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintUndeliveredPrefix,
+                        NostimintUndeliveredKey,
+                        Event,
+                        items,
+                        "Nostimint Undelivered Notes"
+                    );
+                }
+                DbKeyPrefix::SignedNote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintSignedNotePrefix,
+                        NostimintSignedNoteKey,
+                        Event,
+                        items,
+                        "Nostimint Signed Notes"
+                    );
+                }
+                DbKeyPrefix::SignedCount => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintSignedCountPrefix,
+                        NostimintSignedCountKey,
+                        u64,
+                        items,
+                        "Nostimint Signed Note Count"
+                    );
+                }
+                DbKeyPrefix::PublisherCursor => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintPublisherCursorPrefix,
+                        NostimintPublisherCursorKey,
+                        u64,
+                        items,
+                        "Nostimint Publisher Cursor"
+                    );
+                }
+                DbKeyPrefix::RelayTime => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintRelayTimePrefix,
+                        NostimintRelayTimeKey,
+                        (),
+                        items,
+                        "Nostimint Relay Time Index"
+                    );
+                }
+                DbKeyPrefix::RelayKind => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintRelayKindPrefix,
+                        NostimintRelayKindKey,
+                        (),
+                        items,
+                        "Nostimint Relay Kind Index"
+                    );
+                }
+                DbKeyPrefix::RelayAuthor => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintRelayAuthorPrefix,
+                        NostimintRelayAuthorKey,
+                        (),
+                        items,
+                        "Nostimint Relay Author Index"
+                    );
+                }
+                DbKeyPrefix::RelayAuthorCount => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintRelayAuthorCountPrefix,
+                        NostimintRelayAuthorCountKey,
+                        u64,
+                        items,
+                        "Nostimint Relay Author Counts"
+                    );
+                }
+                DbKeyPrefix::RelayBytes => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintRelayBytesPrefix,
+                        NostimintRelayBytesKey,
+                        u64,
+                        items,
+                        "Nostimint Relay Stored Bytes"
+                    );
+                }
+                DbKeyPrefix::RelayCursor => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintRelayCursorPrefix,
+                        NostimintRelayCursorKey,
+                        u64,
+                        items,
+                        "Nostimint Relay Cursor"
+                    );
+                }
+                DbKeyPrefix::KindOwner => {
+                    push_db_pair_items!(
+                        dbtx,
+                        NostimintKindOwnerPrefix,
+                        NostimintKindOwnerKey,
+                        XOnlyPublicKey,
+                        items,
+                        "Nostimint Kind Owners"
+                    );
+                }
             }
         }
 
@@ -233,8 +672,21 @@ impl ServerModuleInit for NostimintGen {
 #[derive(Debug)]
 pub struct Nostimint {
     pub cfg: NostimintConfig,
+    /// Our id, found by matching our share of the nostr key
+    pub our_peer_id: PeerId,
     /// Notifies us to propose an epoch
     pub sign_notify: Notify,
+    /// Secret nonces we committed to and the signing round they are for, kept
+    /// until the note they sign is done or its signing set restarts
+    nonces: Mutex<BTreeMap<EventId, (u64, SecretNonce)>>,
+    /// Profiles our guardian asked to approve, until our approval is processed
+    /// in committed state, they only live in memory like any consensus item
+    /// we are about to propose
+    profile_proposals: Mutex<Vec<FederationProfile>>,
+    /// Decides which notes we refuse to sign
+    policy: Arc<dyn NotePolicy>,
+    /// The session we saw last and when we saw it start
+    session_start: Mutex<(u64, SystemTime)>,
 }
 
 /// Implementation of consensus for the server module
@@ -246,9 +698,12 @@ impl ServerModule for Nostimint {
     type VerificationCache = NostimintVerificationCache;
 
     async fn await_consensus_proposal(&self, dbtx: &mut ModuleDatabaseTransaction<'_>) {
-        // Wait until we have a proposal
+        // Wait until we have a proposal, or it's time to end the session
         if !self.consensus_proposal(dbtx).await.forces_new_epoch() {
-            self.sign_notify.notified().await;
+            tokio::select! {
+                _ = self.sign_notify.notified() => {}
+                _ = sleep(Duration::from_secs(self.cfg.consensus.session_length)) => {}
+            }
         }
     }
 
@@ -256,87 +711,199 @@ impl ServerModule for Nostimint {
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_>,
     ) -> ConsensusProposal<NostimintConsensusItem> {
-        // Check for Kind1's to be signed
-        let sign_requests: Vec<_> = dbtx
-            .find_by_prefix(&NostimintKind1Prefix)
+        // Vote for the time of notes that still need to be built
+        let pending_requests: Vec<_> = dbtx
+            .find_by_prefix(&NostimintNoteRequestPrefix)
             .await
+            .filter_map(|(NostimintNoteRequestKey(request), status)| async move {
+                (status == NoteStatus::Pending).then_some(request)
+            })
             .collect()
             .await;
 
-        // Create a Consensus Item
-        let consensus_items = sign_requests
-            .into_iter()
-            .filter(|(_, sig)| sig.is_none())
-            .map(|(NostimintKind1Key(message), _)| {
-                // TODO: craft nostr note of kind1 and sign wiht FROST key (shoudl the nonce used be part of this?)
-                let my_keys = Keys::from_sk_str(&env::var("NOSTR_PRIVKEY").unwrap()).unwrap();
-                let bech32_pubkey: String = my_keys.public_key().to_bech32().unwrap();
-                println!("Bech32 PubKey: {}", bech32_pubkey);
-                let sig = self.cfg.private.private_key_share.sign(&message);
-                NostimintConsensusItem::Note(message, SerdeSignatureShare(sig))
-            });
-        ConsensusProposal::new_auto_trigger(consensus_items.collect())
-    }
-
-    async fn process_consensus_item<'a, 'b>(
-        &'a self,
-        dbtx: &mut ModuleDatabaseTransaction<'b>,
-        consensus_item: NostimintConsensusItem,
-        peer_id: PeerId,
-    ) -> anyhow::Result<()> {
-        let NostimintConsensusItem::Note(event, share) = consensus_item;
+        let mut consensus_items = vec![];
+        for request in pending_requests {
+            let our_vote = NostimintTimestampKey(request.clone(), self.our_peer_id);
+            let our_veto = NostimintVetoKey(request.clone(), self.our_peer_id);
+            if dbtx.get_value(&our_vote).await.is_some()
+                || dbtx.get_value(&our_veto).await.is_some()
+            {
+                continue;
+            }
 
-        if dbtx
-            .get_value(&NostimintSignatureShareKey(event.clone(), peer_id))
-            .await
-            .is_some()
-        {
-            bail!("Already received a valid signature share")
+            // Zap receipts were paid for by the zap, so they can't be vetoed
+            let requester = dbtx
+                .get_value(&NostimintRequesterKey(request.clone()))
+                .await;
+            let veto = match request.kind {
+                kinds::ZAP_RECEIPT => None,
+                _ => self.policy.veto(&request, requester),
+            };
+            match veto {
+                Some(reason) => {
+                    consensus_items.push(NostimintConsensusItem::NoteVeto(request, reason))
+                }
+                None => {
+                    consensus_items.push(NostimintConsensusItem::NoteRequest(request, unix_now()))
+                }
+            }
         }
 
-        if !self
-            .cfg
-            .consensus
-            .public_key_set
-            .public_key_share(peer_id.to_usize())
-            .verify(&share.0, event.clone())
-        {
-            bail!("Signature share is invalid");
+        // Vote to end the session once it lasted long enough for us
+        let session = dbtx.get_value(&NostimintSessionKey).await.unwrap_or(0);
+        let session_ended = {
+            let mut session_start = self.session_start.lock().expect("poisoned");
+            if session_start.0 != session {
+                *session_start = (session, fedimint_core::time::now());
+            }
+            session_start.1.elapsed().unwrap_or_default()
+                >= Duration::from_secs(self.cfg.consensus.session_length)
+        };
+        let our_session_vote = NostimintSessionVoteKey(session, self.our_peer_id);
+        if session_ended && dbtx.get_value(&our_session_vote).await.is_none() {
+            consensus_items.push(NostimintConsensusItem::SessionVote(session));
         }
 
-        dbtx.insert_new_entry(&NostimintSignatureShareKey(event.clone(), peer_id), &share)
-            .await;
+        // Approve the profiles our guardian asked for, until our approval made it
+        // into committed state or the profile got signed without it
+        let profile_proposals = self.profile_proposals.lock().expect("poisoned").clone();
+        let current_profile = dbtx
+            .get_value(&NostimintProfileKey)
+            .await
+            .and_then(|profile| {
+                serde_json::from_str::<FederationProfile>(&profile.event.content).ok()
+            });
+        for profile in profile_proposals {
+            let approval_key = NostimintProfileApprovalKey(profile.clone(), self.our_peer_id);
+            if dbtx.get_value(&approval_key).await.is_some()
+                || current_profile.as_ref() == Some(&profile)
+            {
+                self.profile_proposals
+                    .lock()
+                    .expect("poisoned")
+                    .retain(|proposal| *proposal != profile);
+                continue;
+            }
+            consensus_items.push(NostimintConsensusItem::ProfileApproval(profile, unix_now()));
+        }
 
-        // Collect all valid signature shares previously received
-        let signature_shares = dbtx
-            .find_by_prefix(&NostimintSignatureShareStringPrefix(event.event.id))
+        // Check for events to be signed
+        let sign_requests: Vec<_> = dbtx
+            .find_by_prefix(&NostimintEventPrefix)
             .await
-            .collect::<Vec<_>>()
+            .filter_map(|(NostimintEventKey(event), signed)| async move {
+                signed.is_none().then_some(event)
+            })
+            .collect()
             .await;
-
-        if signature_shares.len() <= self.cfg.consensus.public_key_set.threshold() {
-            return Ok(());
+        let mut rounds = BTreeMap::new();
+        for event in &sign_requests {
+            let round_key = NostimintSigningRoundKey(NoteId(event.event.id));
+            let round = dbtx.get_value(&round_key).await.unwrap_or(0);
+            rounds.insert(event.event.id, round);
         }
 
-        // let _threshold_signature = self
-        //     .cfg
-        //     .consensus
-        //     .public_key_set
-        //     .combine_signatures(
-        //         signature_shares
-        //             .iter()
-        //             .map(|(peer_id, share)| (peer_id.1.to_usize(), &share.0)),
-        //     )
-        //     .expect("We have verified all signature shares before");
+        // Consensus items can still be rolled back while they are processed, so
+        // nonces are only dropped here, once notes got signed or expired or their
+        // signing round restarted in committed state
+        self.nonces
+            .lock()
+            .expect("poisoned")
+            .retain(|id, (round, _)| rounds.get(id) == Some(round));
 
-        // pretty sure this is incorrect....
-        dbtx.remove_by_prefix(&NostimintSignatureShareStringPrefix(event.event.id))
-            .await;
+        // Create a Consensus Item
+        for event in sign_requests {
+            let id = NoteId(event.event.id);
+            let round = rounds[&event.event.id];
+            let commitments = self.signing_set(dbtx, &event).await;
 
-        dbtx.insert_entry(&NostimintKind1Key(event.clone()), &Some(event))
-            .await;
+            if commitments.len() < self.cfg.consensus.frost_key.threshold() {
+                // Offer to join the signing set until it's complete, unless we
+                // vetoed the note or stalled an earlier set
+                let stalled = dbtx
+                    .get_value(&NostimintStalledKey(id, self.our_peer_id))
+                    .await
+                    .is_some();
+                if !commitments.contains_key(&self.our_peer_id) && !stalled {
+                    let commitment = self
+                        .nonces
+                        .lock()
+                        .expect("poisoned")
+                        .entry(event.event.id)
+                        .or_insert_with(|| (round, SecretNonce::random()))
+                        .1
+                        .commitment();
+                    consensus_items.push(NostimintConsensusItem::Nonce(event, commitment));
+                }
+                continue;
+            }
 
-        Ok(())
+            let our_share_key = NostimintSignatureShareKey(id, self.our_peer_id);
+            if !commitments.contains_key(&self.our_peer_id)
+                || dbtx.get_value(&our_share_key).await.is_some()
+            {
+                continue;
+            }
+
+            // Nonces only live in memory, so after a restart we can't sign for the
+            // sets we joined, they restart without us once their round is over
+            let Some(nonce) = self
+                .nonces
+                .lock()
+                .expect("poisoned")
+                .get(&event.event.id)
+                .map(|(_, nonce)| nonce.clone())
+            else {
+                warn!(event_id = %event.event.id, "Missing secret nonce, can't sign note");
+                continue;
+            };
+
+            match frost::sign(
+                &self.cfg.consensus.frost_key,
+                self.our_peer_id,
+                &self.cfg.private.frost_key_share,
+                nonce,
+                &event.message(),
+                &commitments,
+            ) {
+                Ok(share) => {
+                    consensus_items.push(NostimintConsensusItem::SignatureShare(event, share))
+                }
+                Err(e) => warn!(event_id = %event.event.id, "Failed to sign note: {e}"),
+            }
+        }
+        ConsensusProposal::new_auto_trigger(consensus_items)
+    }
+
+    async fn process_consensus_item<'a, 'b>(
+        &'a self,
+        dbtx: &mut ModuleDatabaseTransaction<'b>,
+        consensus_item: NostimintConsensusItem,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        match consensus_item {
+            NostimintConsensusItem::NoteRequest(request, timestamp) => {
+                self.process_note_request(dbtx, request, timestamp, peer_id)
+                    .await
+            }
+            NostimintConsensusItem::Nonce(event, commitment) => {
+                self.process_nonce(dbtx, event, commitment, peer_id).await
+            }
+            NostimintConsensusItem::SignatureShare(event, share) => {
+                self.process_signature_share(dbtx, event, share, peer_id)
+                    .await
+            }
+            NostimintConsensusItem::ProfileApproval(profile, timestamp) => {
+                self.process_profile_approval(dbtx, profile, timestamp, peer_id)
+                    .await
+            }
+            NostimintConsensusItem::NoteVeto(request, reason) => {
+                self.process_note_veto(dbtx, request, reason, peer_id).await
+            }
+            NostimintConsensusItem::SessionVote(session) => {
+                self.process_session_vote(dbtx, session, peer_id).await
+            }
+        }
     }
 
     fn build_verification_cache<'a>(
@@ -357,25 +924,75 @@ impl ServerModule for Nostimint {
             .await
             .unwrap_or(Amount::ZERO);
 
-        // verify user has enough funds or is using the fed account
-        if input.amount > current_funds && fed_public_key() != input.account {
-            return Err(NostimintError::NotEnoughFunds).into_module_error_other();
-        }
-
-        // Subtract funds from normal user, or print funds for the fed
-        let updated_funds = if fed_public_key() == input.account {
+        // The issuer's funds count how much it printed, other accounts are only
+        // funded by outputs, so they can't spend more than was paid into them
+        let updated_funds = if Some(input.account) == self.cfg.consensus.issuer_key {
             current_funds + input.amount
         } else {
+            if input.amount > current_funds {
+                return Err(NostimintError::NotEnoughFunds).into_module_error_other();
+            }
             current_funds - input.amount
         };
 
         dbtx.insert_entry(&NostimintFundsKeyV1(input.account), &updated_funds)
             .await;
 
+        // Queue the note the input pays for, it gets signed once the tx is accepted.
+        // The account's signature over the request makes it the note's requester.
+        let mut fee = self.cfg.consensus.tx_fee;
+        if let Some(signed) = &input.note {
+            let request = &signed.request;
+            signed
+                .verify(&input.account)
+                .and_then(|_| kinds::validate(request, &self.cfg.consensus.allowed_kinds))
+                .and_then(|_| kinds::validate_namespace(request, input.account))
+                .and_then(|_| {
+                    request.to_unsigned_event(self.cfg.consensus.frost_key.nostr_public_key(), 0)
+                })
+                .map_err(|e| NostimintError::InvalidNote(e.to_string()))
+                .into_module_error_other()?;
+
+            // The first account to get such an event signed owns it
+            if kinds::has_single_owner(request.kind) {
+                let owner_key = NostimintKindOwnerKey(request.kind);
+                match dbtx.get_value(&owner_key).await {
+                    Some(owner) if owner != input.account => {
+                        return Err(NostimintError::KindTaken(request.kind))
+                            .into_module_error_other();
+                    }
+                    Some(_) => {}
+                    None => dbtx.insert_new_entry(&owner_key, &input.account).await,
+                }
+            }
+
+            self.queue_note(dbtx, request).await?;
+            self.charge_quota(dbtx, input.account).await?;
+            dbtx.insert_new_entry(&NostimintRequesterKey(request.clone()), &input.account)
+                .await;
+
+            fee += self.cfg.consensus.note_fee;
+        }
+
+        // Register the name the input pays for, the first tx to claim it wins
+        if let Some(name) = &input.name {
+            nip05::validate_name(name)
+                .map_err(|e| NostimintError::InvalidName(e.to_string()))
+                .into_module_error_other()?;
+
+            let name_key = NostimintNameKey(name.clone());
+            if dbtx.get_value(&name_key).await.is_some() {
+                return Err(NostimintError::NameTaken).into_module_error_other();
+            }
+            dbtx.insert_new_entry(&name_key, &input.account).await;
+
+            fee += self.cfg.consensus.name_fee;
+        }
+
         Ok(InputMeta {
             amount: TransactionItemAmount {
                 amount: input.amount,
-                fee: self.cfg.consensus.tx_fee,
+                fee,
             },
             // IMPORTANT: include the pubkey to validate the user signed this tx
             pub_keys: vec![input.account],
@@ -394,6 +1011,24 @@ impl ServerModule for Nostimint {
         dbtx.insert_entry(&NostimintFundsKeyV1(output.account), &updated_funds)
             .await;
 
+        // Queue the receipt of a zap paid into the account, it gets signed once the
+        // tx is accepted. Zap receipts are authored by the federation, so they
+        // don't count towards anyone's quota.
+        let mut fee = self.cfg.consensus.tx_fee;
+        if let Some(zap_request) = &output.zap {
+            let request = kinds::zap_receipt(zap_request, output.account, output.amount)
+                .and_then(|request| {
+                    request
+                        .to_unsigned_event(self.cfg.consensus.frost_key.nostr_public_key(), 0)?;
+                    Ok(request)
+                })
+                .map_err(|e| NostimintError::InvalidZap(e.to_string()))
+                .into_module_error_other()?;
+            self.queue_note(dbtx, &request).await?;
+
+            fee += self.cfg.consensus.note_fee;
+        }
+
         // Update the output outcome the user can query
         let outcome = NostimintOutputOutcome(updated_funds, output.account);
         dbtx.insert_entry(&NostimintOutcomeKey(out_point), &outcome)
@@ -401,7 +1036,7 @@ impl ServerModule for Nostimint {
 
         Ok(TransactionItemAmount {
             amount: output.amount,
-            fee: self.cfg.consensus.tx_fee,
+            fee,
         })
     }
 
@@ -415,16 +1050,20 @@ impl ServerModule for Nostimint {
     }
 
     async fn audit(&self, dbtx: &mut ModuleDatabaseTransaction<'_>, audit: &mut Audit) {
+        let issuer_key = self.cfg.consensus.issuer_key;
         audit
             .add_items(
                 dbtx,
                 KIND.as_str(),
                 &NostimintFundsPrefixV1,
-                |k, v| match k {
-                    // the fed's test account is considered an asset (positive)
-                    // should be the bitcoin we own in a real module
-                    NostimintFundsKeyV1(key) if key == fed_public_key() => v.msats as i64,
-                    // a user's funds are a federation's liability (negative)
+                move |k, v| match k {
+                    // printed money is backed by nothing, so it's considered an
+                    // asset (positive) to balance the liability the mint counts
+                    // for the ecash it was printed into
+                    NostimintFundsKeyV1(key) if Some(key) == issuer_key => v.msats as i64,
+                    // the mint counts issued ecash as a liability, paying it in
+                    // redeems it and cancels that liability, so the funds we now
+                    // owe the account are our liability (negative)
                     NostimintFundsKeyV1(_) => -(v.msats as i64),
                 },
             )
@@ -434,25 +1073,91 @@ impl ServerModule for Nostimint {
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
         vec![
             api_endpoint! {
-                // API allows users ask the fed to threshold-sign a message into a kind1 nostr note
-                "sign_note",
-                async |module: &Nostimint, context, message: Event| -> EventId {
-                    // TODO: Should not write to DB in module APIs
-                    let mut dbtx = context.dbtx();
-                    // TODO: create event here now
-                    dbtx.insert_entry(&NostimintKind1Key(message.clone()), &None).await;
+                // API waits for the note to be built and signed
+                "wait_signed_note",
+                async |_module: &Nostimint, context, request: NoteRequest| -> Event {
+                    let future = context.wait_value_matches(NostimintNoteRequestKey(request), |status| {
+                        !matches!(status, NoteStatus::Pending | NoteStatus::Signing(_))
+                    });
+                    match future.await {
+                        NoteStatus::Signed(event) => Ok(event),
+                        NoteStatus::Rejected(reason) => Err(ApiError::bad_request(format!("Note was rejected: {reason}"))),
+                        NoteStatus::Expired => Err(ApiError::bad_request("Note expired before it was signed".to_string())),
+                        NoteStatus::Pending | NoteStatus::Signing(_) => unreachable!("checked is decided"),
+                    }
+                }
+            },
+            api_endpoint! {
+                // API waits for an output to be processed, returning the new
+                // balance of the account it paid into
+                "wait_output_outcome",
+                async |_module: &Nostimint, context, out_point: OutPoint| -> NostimintOutputOutcome {
+                    let future = context.wait_key_exists(NostimintOutcomeKey(out_point));
+                    Ok(future.await)
+                }
+            },
+            api_endpoint! {
+                // API returns an account's balance, zero if it was never funded
+                "account_balance",
+                async |_module: &Nostimint, context, account: XOnlyPublicKey| -> Amount {
+                    let balance = context.dbtx().get_value(&NostimintFundsKeyV1(account)).await;
+                    Ok(balance.unwrap_or(Amount::ZERO))
+                }
+            },
+            api_endpoint! {
+                // API returns the current session and how many notes expired
+                "gc_stats",
+                async |_module: &Nostimint, context, _params: ()| -> GcStats {
+                    let stats = context.dbtx().get_value(&NostimintGcStatsKey).await;
+                    Ok(stats.unwrap_or(GcStats {
+                        session: 0,
+                        expired_notes: 0,
+                    }))
+                }
+            },
+            api_endpoint! {
+                // API returns where a note request is in its lifecycle
+                "note_status",
+                async |_module: &Nostimint, context, request: NoteRequest| -> Option<NoteStatus> {
+                    Ok(context.dbtx().get_value(&NostimintNoteRequestKey(request)).await)
+                }
+            },
+            api_endpoint! {
+                // API lets a guardian approve a new federation profile
+                "propose_profile",
+                async |module: &Nostimint, context, profile: FederationProfile| -> () {
+                    if !context.has_auth() {
+                        return Err(ApiError::unauthorized());
+                    }
+                    // Our approval is proposed as a consensus item
+                    let mut proposals = module.profile_proposals.lock().expect("poisoned");
+                    if !proposals.contains(&profile) {
+                        proposals.push(profile);
+                    }
                     module.sign_notify.notify_one();
-                    let event_id = message.event.id;
-                    Ok(event_id)
+                    Ok(())
                 }
             },
             api_endpoint! {
-                // API waits for the signature to exist
-                "wait_signed_note",
-                async |_module: &Nostimint, context, message: Event| -> Event {
-                    let future = context.wait_value_matches(NostimintKind1Key(message), |sig| sig.is_some());
-                    let sig = future.await;
-                    Ok(sig.expect("checked is some"))
+                // API returns the latest signed federation profile
+                "federation_profile",
+                async |_module: &Nostimint, context, _params: ()| -> Option<Event> {
+                    Ok(context.dbtx().get_value(&NostimintProfileKey).await)
+                }
+            },
+            api_endpoint! {
+                // API returns the NIP-05 `.well-known/nostr.json` document, only
+                // with the given name if there is one like `?name=` does
+                "nostr_json",
+                async |module: &Nostimint, context, name: Option<String>| -> Nip05Document {
+                    Ok(module.nostr_json(&mut context.dbtx(), name).await)
+                }
+            },
+            api_endpoint! {
+                // API returns what the relays we published a signed note to answered
+                "note_receipts",
+                async |module: &Nostimint, context, id: NoteId| -> Vec<RelayReceipt> {
+                    Ok(module.note_receipts(&mut context.dbtx(), id).await)
                 }
             },
         ]
@@ -467,21 +1172,1773 @@ impl fedimint_core::server::VerificationCache for NostimintVerificationCache {}
 
 impl Nostimint {
     /// Create new module instance
-    pub fn new(cfg: NostimintConfig) -> Nostimint {
-        Nostimint {
+    pub fn new(cfg: NostimintConfig, policy: Arc<dyn NotePolicy>) -> anyhow::Result<Nostimint> {
+        let Some(our_peer_id) = cfg
+            .consensus
+            .frost_key
+            .peer_of(&cfg.private.frost_key_share)
+        else {
+            bail!("Our nostr key share is not part of the federation key");
+        };
+
+        Ok(Nostimint {
             cfg,
+            our_peer_id,
             sign_notify: Notify::new(),
+            nonces: Mutex::new(BTreeMap::new()),
+            profile_proposals: Mutex::new(vec![]),
+            policy,
+            session_start: Mutex::new((0, fedimint_core::time::now())),
+        })
+    }
+
+    /// The NIP-05 document of the registered names, only with `name` if given
+    async fn nostr_json(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        name: Option<String>,
+    ) -> Nip05Document {
+        let names = match name {
+            Some(name) => dbtx
+                .get_value(&NostimintNameKey(name.clone()))
+                .await
+                .map(|account| (name, account))
+                .into_iter()
+                .collect(),
+            None => {
+                dbtx.find_by_prefix(&NostimintNamePrefix)
+                    .await
+                    .map(|(NostimintNameKey(name), account)| (name, account))
+                    .collect()
+                    .await
+            }
+        };
+        Nip05Document { names }
+    }
+
+    /// What the relays our guardian published a signed note to answered
+    async fn note_receipts(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        id: NoteId,
+    ) -> Vec<RelayReceipt> {
+        dbtx.find_by_prefix(&NostimintReceiptNotePrefix(id))
+            .await
+            .map(|(_, receipt)| receipt)
+            .collect()
+            .await
+    }
+
+    /// Nonce commitments of the peers that joined the signing set of a note
+    async fn signing_set(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        event: &UnsignedEvent,
+    ) -> BTreeMap<PeerId, NonceCommitment> {
+        dbtx.find_by_prefix(&NostimintNonceNotePrefix(NoteId(event.event.id)))
+            .await
+            .map(|(NostimintNonceKey(_, peer), commitment)| (peer, commitment))
+            .collect()
+            .await
+    }
+
+    /// Signature shares received so far for a note
+    async fn signature_shares(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        event: &UnsignedEvent,
+    ) -> BTreeMap<PeerId, FrostSignatureShare> {
+        dbtx.find_by_prefix(&NostimintSignatureShareNotePrefix(NoteId(event.event.id)))
+            .await
+            .map(|(NostimintSignatureShareKey(_, peer), share)| (peer, share))
+            .collect()
+            .await
+    }
+
+    /// Votes received so far for the time of a note
+    async fn timestamp_votes(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        request: &NoteRequest,
+    ) -> BTreeMap<PeerId, u64> {
+        let mut votes = BTreeMap::new();
+        for &peer in self.cfg.consensus.frost_key.public_shares.keys() {
+            let key = NostimintTimestampKey(request.clone(), peer);
+            if let Some(vote) = dbtx.get_value(&key).await {
+                votes.insert(peer, vote);
+            }
+        }
+        votes
+    }
+
+    async fn process_note_request(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        request: NoteRequest,
+        timestamp: u64,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        match dbtx
+            .get_value(&NostimintNoteRequestKey(request.clone()))
+            .await
+        {
+            // Requests are only queued by the transaction paying for them
+            None => bail!("Note request was not paid for"),
+            Some(NoteStatus::Pending) => {}
+            Some(_) => bail!("Note request was already decided"),
+        }
+
+        // Zap receipts are built by the federation from a zap request it checked
+        if request.kind != kinds::ZAP_RECEIPT {
+            kinds::validate(&request, &self.cfg.consensus.allowed_kinds)?;
+        }
+
+        let vote_key = NostimintTimestampKey(request.clone(), peer_id);
+        if dbtx.get_value(&vote_key).await.is_some() {
+            bail!("Already received a timestamp vote");
+        }
+        let veto_key = NostimintVetoKey(request.clone(), peer_id);
+        if dbtx.get_value(&veto_key).await.is_some() {
+            bail!("Peer already vetoed the note");
+        }
+
+        dbtx.insert_new_entry(&vote_key, &timestamp).await;
+
+        let votes = self.timestamp_votes(dbtx, &request).await;
+        if votes.len() < self.cfg.consensus.frost_key.threshold() {
+            return Ok(());
+        }
+
+        // The median of a threshold of votes is bounded by honest peers' clocks
+        let mut timestamps = votes.values().copied().collect::<Vec<_>>();
+        timestamps.sort_unstable();
+        let created_at = timestamps[timestamps.len() / 2];
+
+        let mut vetoers = vec![];
+        for &peer in self.cfg.consensus.frost_key.public_shares.keys() {
+            let veto_key = NostimintVetoKey(request.clone(), peer);
+            if dbtx.get_value(&veto_key).await.is_some() {
+                vetoers.push(peer);
+            }
+        }
+        self.remove_votes(dbtx, &request).await;
+
+        // Zap receipts carry the time of the user's zap request, but are
+        // authored by the federation at the agreed time, so only user notes
+        // can be stale
+        if request.kind != kinds::ZAP_RECEIPT
+            && created_at.abs_diff(request.created_at) > self.cfg.consensus.timestamp_tolerance
+        {
+            warn!(
+                created_at,
+                requested_at = request.created_at,
+                "Rejecting stale or future-dated note request"
+            );
+            self.reject_note(dbtx, request, "Stale or future-dated".to_string())
+                .await;
+            return Ok(());
+        }
+
+        // Guardians that vetoed the note don't help sign it
+        let id = self.build_note(dbtx, request, created_at).await?;
+        for peer in vetoers {
+            dbtx.insert_new_entry(&NostimintStalledKey(id, peer), &())
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn process_note_veto(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        request: NoteRequest,
+        reason: String,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        match dbtx
+            .get_value(&NostimintNoteRequestKey(request.clone()))
+            .await
+        {
+            None => bail!("Note request was not paid for"),
+            Some(NoteStatus::Pending) => {}
+            Some(_) => bail!("Note request was already decided"),
+        }
+        if request.kind == kinds::ZAP_RECEIPT {
+            bail!("Zap receipts were paid for by the zap and can't be vetoed");
+        }
+
+        let veto_key = NostimintVetoKey(request.clone(), peer_id);
+        if dbtx.get_value(&veto_key).await.is_some() {
+            bail!("Peer already vetoed the note");
+        }
+        let vote_key = NostimintTimestampKey(request.clone(), peer_id);
+        if dbtx.get_value(&vote_key).await.is_some() {
+            bail!("Peer already voted for the note");
+        }
+        dbtx.insert_new_entry(&veto_key, &reason).await;
+
+        // Reject once too many guardians vetoed for a threshold to approve
+        let peers = self.cfg.consensus.frost_key.public_shares.keys();
+        let mut vetoes = 0;
+        for &peer in peers.clone() {
+            if dbtx
+                .get_value(&NostimintVetoKey(request.clone(), peer))
+                .await
+                .is_some()
+            {
+                vetoes += 1;
+            }
+        }
+        if vetoes + self.cfg.consensus.frost_key.threshold() > peers.len() {
+            warn!(%peer_id, %reason, "Rejecting vetoed note request");
+            self.remove_votes(dbtx, &request).await;
+            self.reject_note(dbtx, request, format!("Vetoed: {reason}"))
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn process_session_vote(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        session: u64,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        let current = dbtx.get_value(&NostimintSessionKey).await.unwrap_or(0);
+        if session != current {
+            bail!("Vote is not for the current session");
+        }
+        let vote_key = NostimintSessionVoteKey(session, peer_id);
+        if dbtx.get_value(&vote_key).await.is_some() {
+            bail!("Already received a vote to end the session");
+        }
+        dbtx.insert_new_entry(&vote_key, &()).await;
+
+        let mut votes = vec![];
+        for &peer in self.cfg.consensus.frost_key.public_shares.keys() {
+            let key = NostimintSessionVoteKey(session, peer);
+            if dbtx.get_value(&key).await.is_some() {
+                votes.push(key);
+            }
+        }
+        if votes.len() < self.cfg.consensus.frost_key.threshold() {
+            return Ok(());
+        }
+
+        for key in votes {
+            dbtx.remove_entry(&key).await;
+        }
+        dbtx.insert_entry(&NostimintSessionKey, &(session + 1))
+            .await;
+        self.restart_stalled_signing(dbtx, session + 1).await;
+        self.expire_notes(dbtx, session + 1).await;
+
+        Ok(())
+    }
+
+    /// Expires note requests that weren't signed within the expiry sessions,
+    /// every peer prunes the same ones since sessions end in consensus
+    ///
+    /// An expired request only keeps its status, as a tombstone that tells
+    /// waiting clients it expired, which is dropped after another expiry. The
+    /// status of rejected requests is dropped once they'd have expired.
+    async fn expire_notes(&self, dbtx: &mut ModuleDatabaseTransaction<'_>, session: u64) {
+        let expiry_sessions = self.cfg.consensus.expiry_sessions;
+        let stale_requests: Vec<_> = dbtx
+            .find_by_prefix(&NostimintQueuedPrefix)
+            .await
+            .filter_map(|(NostimintQueuedKey(request), queued)| async move {
+                (queued + expiry_sessions <= session).then_some((request, queued))
+            })
+            .collect()
+            .await;
+
+        let mut expired_notes = 0;
+        for (request, queued) in stale_requests {
+            dbtx.remove_entry(&NostimintQueuedKey(request.clone()))
+                .await;
+
+            match dbtx
+                .get_value(&NostimintNoteRequestKey(request.clone()))
+                .await
+            {
+                Some(NoteStatus::Pending) => {
+                    self.remove_votes(dbtx, &request).await;
+                }
+                Some(NoteStatus::Signing(event)) => {
+                    let id = NoteId(event.event.id);
+                    dbtx.remove_by_prefix(&NostimintNonceNotePrefix(id)).await;
+                    dbtx.remove_by_prefix(&NostimintSignatureShareNotePrefix(id))
+                        .await;
+                    dbtx.remove_entry(&NostimintEventRequestKey(id)).await;
+                    dbtx.remove_entry(&NostimintSigningRoundKey(id)).await;
+                    dbtx.remove_by_prefix(&NostimintStalledNotePrefix(id)).await;
+                    dbtx.remove_entry(&NostimintEventKey(event)).await;
+                }
+                Some(NoteStatus::Expired | NoteStatus::Rejected(_)) => {
+                    dbtx.remove_entry(&NostimintNoteRequestKey(request)).await;
+                    continue;
+                }
+                // Signed notes are kept for clients and relays
+                Some(NoteStatus::Signed(_)) | None => continue,
+            }
+
+            warn!(?request, "Note request expired");
+            self.remove_approvals(dbtx, &request).await;
+            self.refund_note(dbtx, &request, queued).await;
+            dbtx.insert_entry(
+                &NostimintNoteRequestKey(request.clone()),
+                &NoteStatus::Expired,
+            )
+            .await;
+            dbtx.insert_entry(&NostimintQueuedKey(request), &session)
+                .await;
+            expired_notes += 1;
+        }
+
+        let mut stats = dbtx
+            .get_value(&NostimintGcStatsKey)
+            .await
+            .unwrap_or(GcStats {
+                session: 0,
+                expired_notes: 0,
+            });
+        stats.session = session;
+        stats.expired_notes += expired_notes;
+        dbtx.insert_entry(&NostimintGcStatsKey, &stats).await;
+    }
+
+    /// Restarts the signing sets that didn't sign their note within a whole
+    /// session, without the members that didn't send a share
+    ///
+    /// Secret nonces only live in memory, so a single guardian that restarted
+    /// or stopped signing would otherwise stall the note until it expires.
+    async fn restart_stalled_signing(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        session: u64,
+    ) {
+        let stale_rounds: Vec<_> = dbtx
+            .find_by_prefix(&NostimintSigningRoundPrefix)
+            .await
+            .filter_map(|(NostimintSigningRoundKey(id), started)| async move {
+                (started + 1 < session).then_some(id)
+            })
+            .collect()
+            .await;
+
+        for id in stale_rounds {
+            let signing_set: Vec<PeerId> = dbtx
+                .find_by_prefix(&NostimintNonceNotePrefix(id))
+                .await
+                .map(|(NostimintNonceKey(_, peer), _)| peer)
+                .collect()
+                .await;
+            // Incomplete sets are still waiting for peers to join
+            if signing_set.len() < self.cfg.consensus.frost_key.threshold() {
+                continue;
+            }
+            let signed: BTreeSet<PeerId> = dbtx
+                .find_by_prefix(&NostimintSignatureShareNotePrefix(id))
+                .await
+                .map(|(NostimintSignatureShareKey(_, peer), _)| peer)
+                .collect()
+                .await;
+
+            let stalled: Vec<_> = signing_set
+                .into_iter()
+                .filter(|peer| !signed.contains(peer))
+                .collect();
+            warn!(note_id = %id.0, ?stalled, "Restarting stalled signing set");
+            for peer in stalled {
+                dbtx.insert_entry(&NostimintStalledKey(id, peer), &()).await;
+            }
+
+            // Our new commitment must come with a new nonce, reusing one for a
+            // different signing set would leak our key share, the new round
+            // makes us drop the old nonce
+            dbtx.remove_by_prefix(&NostimintNonceNotePrefix(id)).await;
+            dbtx.remove_by_prefix(&NostimintSignatureShareNotePrefix(id))
+                .await;
+            dbtx.insert_entry(&NostimintSigningRoundKey(id), &session)
+                .await;
+            self.sign_notify.notify_one();
+        }
+    }
+
+    /// Removes the time votes and vetoes once a note request is decided
+    async fn remove_votes(&self, dbtx: &mut ModuleDatabaseTransaction<'_>, request: &NoteRequest) {
+        for &peer in self.cfg.consensus.frost_key.public_shares.keys() {
+            dbtx.remove_entry(&NostimintTimestampKey(request.clone(), peer))
+                .await;
+            dbtx.remove_entry(&NostimintVetoKey(request.clone(), peer))
+                .await;
+        }
+    }
+
+    /// Marks a note request that won't be signed, waiting clients learn why
+    async fn reject_note(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        request: NoteRequest,
+        reason: String,
+    ) {
+        let session = dbtx.get_value(&NostimintSessionKey).await.unwrap_or(0);
+        let queued = dbtx
+            .get_value(&NostimintQueuedKey(request.clone()))
+            .await
+            .unwrap_or(session);
+        self.refund_note(dbtx, &request, queued).await;
+        dbtx.insert_entry(
+            &NostimintNoteRequestKey(request),
+            &NoteStatus::Rejected(reason),
+        )
+        .await;
+    }
+
+    /// Gives the note fee and quota slot back to the account that paid for a
+    /// note that won't be signed, `queued` is the session it was paid in
+    ///
+    /// Whether a note is rejected for its time or vetoed is only known after
+    /// the tx paying for it was accepted, so the fee can't be refused upfront.
+    /// The tx fee isn't refunded, the tx was processed after all.
+    async fn refund_note(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        request: &NoteRequest,
+        queued: u64,
+    ) {
+        let requester_key = NostimintRequesterKey(request.clone());
+        // Only paid notes have a requester, zap receipts and profiles don't
+        let Some(account) = dbtx.get_value(&requester_key).await else {
+            return;
+        };
+        dbtx.remove_entry(&requester_key).await;
+
+        let funds_key = NostimintFundsKeyV1(account);
+        let funds = dbtx.get_value(&funds_key).await.unwrap_or(Amount::ZERO);
+        dbtx.insert_entry(&funds_key, &(funds + self.cfg.consensus.note_fee))
+            .await;
+
+        let window = self
+            .cfg
+            .consensus
+            .note_quota
+            .window_of(queued, self.cfg.consensus.session_length);
+        let quota_key = NostimintQuotaKey(account, window);
+        if let Some(used) = dbtx.get_value(&quota_key).await {
+            if used > 1 {
+                dbtx.insert_entry(&quota_key, &(used - 1)).await;
+            } else {
+                dbtx.remove_entry(&quota_key).await;
+            }
+        }
+    }
+
+    /// Queues a paid note request, so guardians vote on its time or veto it
+    async fn queue_note(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        request: &NoteRequest,
+    ) -> Result<(), ModuleError> {
+        let request_key = NostimintNoteRequestKey(request.clone());
+        if dbtx.get_value(&request_key).await.is_some() {
+            return Err(NostimintError::DuplicateNote).into_module_error_other();
+        }
+        dbtx.insert_new_entry(&request_key, &NoteStatus::Pending)
+            .await;
+        let session = dbtx.get_value(&NostimintSessionKey).await.unwrap_or(0);
+        dbtx.insert_new_entry(&NostimintQueuedKey(request.clone()), &session)
+            .await;
+        self.sign_notify.notify_one();
+
+        Ok(())
+    }
+
+    /// Counts a note paid for in the current session towards the account's
+    /// quota, rejecting it once exhausted
+    async fn charge_quota(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        account: XOnlyPublicKey,
+    ) -> Result<(), ModuleError> {
+        let quota = self.cfg.consensus.note_quota;
+        let session = dbtx.get_value(&NostimintSessionKey).await.unwrap_or(0);
+        let window = quota.window_of(session, self.cfg.consensus.session_length);
+
+        // Counts of past windows are no longer needed
+        let stale_windows: Vec<_> = dbtx
+            .find_by_prefix(&NostimintQuotaAccountPrefix(account))
+            .await
+            .filter_map(|(key, _)| async move { (key.1 < window).then_some(key) })
+            .collect()
+            .await;
+        for key in stale_windows {
+            dbtx.remove_entry(&key).await;
+        }
+
+        let key = NostimintQuotaKey(account, window);
+        let used = dbtx.get_value(&key).await.unwrap_or(0);
+        if used >= quota.max_notes {
+            return Err(NostimintError::QuotaExceeded(used)).into_module_error_other();
+        }
+        dbtx.insert_entry(&key, &(used + 1)).await;
+
+        Ok(())
+    }
+
+    /// Builds the event of a note request at the agreed time so it gets signed
+    async fn build_note(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        request: NoteRequest,
+        created_at: u64,
+    ) -> anyhow::Result<NoteId> {
+        let event = request
+            .to_unsigned_event(self.cfg.consensus.frost_key.nostr_public_key(), created_at)?;
+
+        // Profiles aren't paid for, so their expiry starts once they are built
+        let session = dbtx.get_value(&NostimintSessionKey).await.unwrap_or(0);
+        if dbtx
+            .get_value(&NostimintQueuedKey(request.clone()))
+            .await
+            .is_none()
+        {
+            dbtx.insert_new_entry(&NostimintQueuedKey(request.clone()), &session)
+                .await;
+        }
+
+        let id = NoteId(event.event.id);
+        dbtx.insert_entry(&NostimintEventRequestKey(id), &request)
+            .await;
+        dbtx.insert_entry(&NostimintSigningRoundKey(id), &session)
+            .await;
+        dbtx.insert_entry(
+            &NostimintNoteRequestKey(request),
+            &NoteStatus::Signing(event.clone()),
+        )
+        .await;
+        dbtx.insert_entry(&NostimintEventKey(event), &None).await;
+
+        // Now every peer can offer to join the signing set
+        self.sign_notify.notify_one();
+
+        Ok(id)
+    }
+
+    /// Approvals received so far for a profile and their time votes
+    async fn profile_approvals(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        profile: &FederationProfile,
+    ) -> BTreeMap<PeerId, u64> {
+        dbtx.find_by_prefix(&NostimintProfileApprovalProfilePrefix(profile.clone()))
+            .await
+            .map(|(NostimintProfileApprovalKey(_, peer), timestamp)| (peer, timestamp))
+            .collect()
+            .await
+    }
+
+    /// Drops the approvals of a profile once its note is signed or expired
+    async fn remove_approvals(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        request: &NoteRequest,
+    ) {
+        if let Some(profile) = FederationProfile::from_note_request(request) {
+            dbtx.remove_by_prefix(&NostimintProfileApprovalProfilePrefix(profile))
+                .await;
+        }
+    }
+
+    async fn process_profile_approval(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        profile: FederationProfile,
+        timestamp: u64,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        let approval_key = NostimintProfileApprovalKey(profile.clone(), peer_id);
+        if dbtx.get_value(&approval_key).await.is_some() {
+            bail!("Already received an approval of this profile");
+        }
+
+        // Approvals arriving after a threshold approved the profile are late,
+        // they would otherwise linger or get the same profile signed again
+        let current = dbtx.get_value(&NostimintProfileKey).await;
+        if current.map_or(false, |current| {
+            serde_json::from_str::<FederationProfile>(&current.event.content).ok()
+                == Some(profile.clone())
+        }) {
+            debug!(%peer_id, "Ignoring approval of the current profile");
+            return Ok(());
+        }
+        let approvals = self.profile_approvals(dbtx, &profile).await;
+        if approvals.len() >= self.cfg.consensus.frost_key.threshold() {
+            debug!(%peer_id, "Ignoring approval of a profile that is being signed");
+            return Ok(());
+        }
+
+        dbtx.insert_new_entry(&approval_key, &timestamp).await;
+        let approvals = self.profile_approvals(dbtx, &profile).await;
+        if approvals.len() < self.cfg.consensus.frost_key.threshold() {
+            return Ok(());
+        }
+
+        // Like note requests, the median of the votes is bounded by honest peers' clocks.
+        // The approvals are kept until the profile is signed or expires.
+        let mut timestamps = approvals.values().copied().collect::<Vec<_>>();
+        timestamps.sort_unstable();
+        let created_at = timestamps[timestamps.len() / 2];
+
+        let request = profile.to_note_request(created_at);
+        self.build_note(dbtx, request, created_at).await?;
+        Ok(())
+    }
+
+    async fn process_nonce(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        event: UnsignedEvent,
+        commitment: NonceCommitment,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        // Only sign events we built ourselves from a note request
+        match dbtx.get_value(&NostimintEventKey(event.clone())).await {
+            None => bail!("Note was not built by the federation"),
+            Some(Some(_)) => bail!("Note is already signed"),
+            Some(None) => {}
+        }
+
+        let id = NoteId(event.event.id);
+        let nonce_key = NostimintNonceKey(id, peer_id);
+        if dbtx.get_value(&nonce_key).await.is_some() {
+            bail!("Already received a nonce commitment");
+        }
+        if dbtx
+            .get_value(&NostimintStalledKey(id, peer_id))
+            .await
+            .is_some()
+        {
+            bail!("Peer vetoed the note or stalled an earlier signing set of it");
+        }
+
+        let signing_set = self.signing_set(dbtx, &event).await;
+        if signing_set.len() >= self.cfg.consensus.frost_key.threshold() {
+            bail!("Signing set is already complete");
+        }
+
+        dbtx.insert_new_entry(&nonce_key, &commitment).await;
+
+        // Once the signing set is complete its members can propose shares
+        if signing_set.len() + 1 == self.cfg.consensus.frost_key.threshold() {
+            self.sign_notify.notify_one();
+        }
+
+        Ok(())
+    }
+
+    async fn process_signature_share(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        event: UnsignedEvent,
+        share: FrostSignatureShare,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        let id = NoteId(event.event.id);
+        let share_key = NostimintSignatureShareKey(id, peer_id);
+        if dbtx.get_value(&share_key).await.is_some() {
+            bail!("Already received a valid signature share")
+        }
+
+        let commitments = self.signing_set(dbtx, &event).await;
+        frost::verify_share(
+            &self.cfg.consensus.frost_key,
+            peer_id,
+            &share,
+            &event.message(),
+            &commitments,
+        )?;
+
+        dbtx.insert_new_entry(&share_key, &share).await;
+
+        // Collect all valid signature shares previously received
+        let signature_shares = self.signature_shares(dbtx, &event).await;
+        if signature_shares.len() < self.cfg.consensus.frost_key.threshold() {
+            return Ok(());
+        }
+
+        // Every share was verified, so this only fails if the note itself is
+        // broken, which rolls back the share and leaves the set to be restarted
+        let signature = frost::combine(
+            &self.cfg.consensus.frost_key,
+            &event.message(),
+            &commitments,
+            &signature_shares,
+        )?;
+        let signed = event.add_signature(signature)?;
+
+        dbtx.remove_by_prefix(&NostimintNonceNotePrefix(id)).await;
+        dbtx.remove_by_prefix(&NostimintSignatureShareNotePrefix(id))
+            .await;
+        dbtx.remove_entry(&NostimintSigningRoundKey(id)).await;
+        dbtx.remove_by_prefix(&NostimintStalledNotePrefix(id)).await;
+
+        // Only guardians can get metadata signed, so it is the federation's profile
+        if signed.event.kind == Kind::Metadata {
+            let current = dbtx.get_value(&NostimintProfileKey).await;
+            if current.map_or(true, |current| {
+                current.event.created_at < signed.event.created_at
+            }) {
+                dbtx.insert_entry(&NostimintProfileKey, &signed).await;
+            }
+        }
+
+        let request_key = NostimintEventRequestKey(id);
+        if let Some(request) = dbtx.get_value(&request_key).await {
+            self.remove_approvals(dbtx, &request).await;
+            dbtx.insert_entry(
+                &NostimintNoteRequestKey(request),
+                &NoteStatus::Signed(signed.clone()),
+            )
+            .await;
         }
+
+        // Our publisher and relay follow the signed notes once this is
+        // committed, so every peer records the same ones whatever it is
+        // configured to do with them
+        let position = dbtx.get_value(&NostimintSignedCountKey).await.unwrap_or(0);
+        dbtx.insert_new_entry(&NostimintSignedNoteKey(position), &signed)
+            .await;
+        dbtx.insert_entry(&NostimintSignedCountKey, &(position + 1))
+            .await;
+        dbtx.insert_entry(&NostimintEventKey(event), &Some(signed))
+            .await;
+
+        Ok(())
     }
 }
 
-// fn publish_to_relay(relay: &str, message: &websocket::Message) -> Result<(), String> {
-//     let mut client = ClientBuilder::new(relay)
-//         .map_err(|err| format!("Could not create client: {}", err.to_string()))?
-//         .connect(None)
-//         .map_err(|err| format!("Could not connect to relay {}: {}", relay, err.to_string()))?;
-//     client
-//         .send_message(message)
-//         .map_err(|err| format!("could not send message to relay: {}", err.to_string()))?;
-//     Ok(())
-// }
+/// Current unix time in seconds
+fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time is after the epoch")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::Hash;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::TransactionId;
+    use fedimint_nostimint_common::frost::FrostPublicKeySet;
+    use nostr_sdk::{EventBuilder, Keys, Kind, Tag};
+    use rand::rngs::OsRng;
+    use secp256k1::{KeyPair, SecretKey};
+    use threshold_crypto::serde_impl::SerdeSecret;
+    use threshold_crypto::SecretKeySet;
+
+    use super::*;
+
+    /// The module of a federation with a single guardian and default params
+    pub(crate) fn single_guardian() -> Nostimint {
+        guardians(1, 1).remove(0)
+    }
+
+    /// The modules of each guardian of a federation with default params, that
+    /// needs `threshold` of them to sign
+    pub(crate) fn guardians(peers: u16, threshold: usize) -> Vec<Nostimint> {
+        let keys = SecretKeySet::random(threshold - 1, &mut OsRng);
+        let dealers = (0..peers)
+            .map(|peer| DkgDealer::new(PeerId::from(peer), threshold))
+            .collect::<Vec<_>>();
+        let commitments = (0..peers)
+            .map(PeerId::from)
+            .zip(dealers.iter().map(DkgDealer::commitment))
+            .collect::<BTreeMap<_, _>>();
+        let dealt = dealers
+            .iter()
+            .map(|dealer| dealer.encrypted_shares(&commitments))
+            .collect::<Vec<_>>();
+
+        dealers
+            .into_iter()
+            .enumerate()
+            .map(|(peer, dealer)| {
+                let shares = (0..peers)
+                    .map(PeerId::from)
+                    .zip(
+                        dealt
+                            .iter()
+                            .map(|shares| shares[&PeerId::from(peer as u16)].clone()),
+                    )
+                    .collect();
+                let (frost_key, frost_key_share) = dealer
+                    .finish(&commitments, &shares)
+                    .expect("every dealer is honest");
+                guardian(frost_key, frost_key_share, keys.secret_key_share(peer))
+            })
+            .collect()
+    }
+
+    /// The module of a guardian with its shares of the federation's keys
+    fn guardian(
+        frost_key: FrostPublicKeySet,
+        frost_key_share: SecretKey,
+        private_key_share: threshold_crypto::SecretKeyShare,
+    ) -> Nostimint {
+        let params = NostimintGenParams::default();
+        let cfg = NostimintConfig {
+            local: NostimintConfigLocal {
+                example: params.local.example,
+                relays: params.local.relays,
+                publish_attempts: params.local.publish_attempts,
+                policy: params.local.policy.clone(),
+                relay_bind: params.local.relay_bind,
+            },
+            private: NostimintConfigPrivate {
+                private_key_share: SerdeSecret(private_key_share),
+                frost_key_share,
+            },
+            consensus: NostimintConfigConsensus {
+                public_key_set: keys.public_keys(),
+                frost_key,
+                tx_fee: params.consensus.tx_fee,
+                note_fee: params.consensus.note_fee,
+                name_fee: params.consensus.name_fee,
+                timestamp_tolerance: params.consensus.timestamp_tolerance,
+                allowed_kinds: params.consensus.allowed_kinds,
+                note_quota: params.consensus.note_quota,
+                session_length: params.consensus.session_length,
+                expiry_sessions: params.consensus.expiry_sessions,
+                issuer_key: params.consensus.issuer_key,
+            },
+        };
+        Nostimint::new(cfg, Arc::new(params.local.policy)).expect("our share is part of the key")
+    }
+
+    /// An empty database for the module
+    pub(crate) fn memory_db() -> Database {
+        Database::new(MemDatabase::new(), ModuleDecoderRegistry::default())
+    }
+
+    /// The key of a new account
+    pub(crate) fn account_key() -> KeyPair {
+        KeyPair::from_seckey_slice(&Secp256k1::new(), &rand::random::<[u8; 32]>())
+            .expect("random bytes are a valid key")
+    }
+
+    /// Runs consensus between the guardians until none of them proposes
+    /// anything, each processing every item in its own database
+    ///
+    /// Like in consensus, items that fail to process are dropped.
+    async fn run_consensus(guardians: &[Nostimint], dbs: &[Database]) {
+        for _ in 0..10 {
+            let mut items = vec![];
+            for (module, db) in guardians.iter().zip(dbs) {
+                let mut dbtx = db.begin_transaction().await;
+                let proposal = module
+                    .consensus_proposal(&mut dbtx.with_module_prefix(0))
+                    .await;
+                dbtx.commit_tx().await;
+                items.extend(
+                    proposal
+                        .items
+                        .into_iter()
+                        .map(|item| (module.our_peer_id, item)),
+                );
+            }
+            if items.is_empty() {
+                return;
+            }
+
+            for (module, db) in guardians.iter().zip(dbs) {
+                for (peer, item) in &items {
+                    let mut dbtx = db.begin_transaction().await;
+                    let processed = module
+                        .process_consensus_item(
+                            &mut dbtx.with_module_prefix(0),
+                            item.clone(),
+                            *peer,
+                        )
+                        .await;
+                    if processed.is_ok() {
+                        dbtx.commit_tx().await;
+                    }
+                }
+            }
+        }
+        panic!("Guardians kept proposing items");
+    }
+
+    /// An input paying for a text note signed by the account
+    fn text_note(module: &Nostimint, key: &KeyPair, content: &str) -> NostimintInput {
+        NostimintInput {
+            amount: module.cfg.consensus.note_fee + module.cfg.consensus.tx_fee,
+            account: key.x_only_public_key().0,
+            note: Some(
+                NoteRequest {
+                    kind: kinds::TEXT_NOTE,
+                    content: content.to_string(),
+                    tags: vec![],
+                    created_at: unix_now(),
+                }
+                .sign(key),
+            ),
+            name: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn signs_paid_notes_at_an_agreed_time() -> anyhow::Result<()> {
+        let guardians = guardians(3, 2);
+        let dbs = vec![memory_db(), memory_db(), memory_db()];
+        let key = account_key();
+        let account = key.x_only_public_key().0;
+        let input = text_note(&guardians[0], &key, "hello nostr");
+        let request = input.note.clone().expect("input pays for a note").request;
+
+        // Every guardian accepts the tx paying for the note
+        for (module, db) in guardians.iter().zip(&dbs) {
+            let mut dbtx = db.begin_transaction().await;
+            {
+                let mut dbtx = dbtx.with_module_prefix(0);
+                dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::from_sats(10))
+                    .await;
+                let meta = module
+                    .process_input(&mut dbtx, &input, &NostimintVerificationCache)
+                    .await
+                    .expect("account paid for the note");
+                assert_eq!(
+                    meta.amount.fee,
+                    module.cfg.consensus.tx_fee + module.cfg.consensus.note_fee
+                );
+            }
+            dbtx.commit_tx().await;
+        }
+
+        run_consensus(&guardians, &dbs).await;
+
+        // Every guardian recorded the same note signed by the federation
+        let mut signed_notes = vec![];
+        for db in &dbs {
+            let mut dbtx = db.begin_transaction().await;
+            let mut dbtx = dbtx.with_module_prefix(0);
+            let Some(NoteStatus::Signed(event)) = dbtx
+                .get_value(&NostimintNoteRequestKey(request.clone()))
+                .await
+            else {
+                anyhow::bail!("Expected the note to be signed");
+            };
+            assert_eq!(
+                dbtx.get_value(&NostimintSignedNoteKey(0)).await,
+                Some(event.clone())
+            );
+            signed_notes.push(event);
+        }
+        assert!(signed_notes.windows(2).all(|notes| notes[0] == notes[1]));
+
+        let event = &signed_notes[0];
+        let federation_key = guardians[0].cfg.consensus.frost_key.nostr_public_key();
+        event.verify_federation_signature(XOnlyPublicKey::from_slice(
+            &federation_key.serialize(),
+        )?)?;
+        assert_eq!(event.event.content, "hello nostr");
+        assert!(
+            event.event.created_at.as_u64().abs_diff(request.created_at)
+                <= guardians[0].cfg.consensus.timestamp_tolerance
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn agrees_on_the_median_time() -> anyhow::Result<()> {
+        let module = guardians(3, 3).remove(0);
+        let db = memory_db();
+        let key = account_key();
+        let account = key.x_only_public_key().0;
+        let tolerance = module.cfg.consensus.timestamp_tolerance;
+        let (timely, stale) = (
+            text_note(&module, &key, "timely"),
+            text_note(&module, &key, "stale"),
+        );
+        let request = |input: &NostimintInput| input.note.clone().expect("pays for a note").request;
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::from_sats(10))
+                .await;
+            for input in [&timely, &stale] {
+                module
+                    .process_input(&mut dbtx, input, &NostimintVerificationCache)
+                    .await
+                    .expect("account paid for the note");
+            }
+
+            // A single guardian with a wrong clock can't move the agreed time
+            let requested_at = request(&timely).created_at;
+            let votes = [
+                requested_at - 5,
+                requested_at,
+                requested_at + 10 * tolerance,
+            ];
+            for (peer, vote) in votes.into_iter().enumerate() {
+                module
+                    .process_note_request(
+                        &mut dbtx,
+                        request(&timely),
+                        vote,
+                        PeerId::from(peer as u16),
+                    )
+                    .await?;
+            }
+            match dbtx
+                .get_value(&NostimintNoteRequestKey(request(&timely)))
+                .await
+            {
+                Some(NoteStatus::Signing(event)) => {
+                    assert_eq!(event.event.created_at.as_u64(), requested_at)
+                }
+                status => anyhow::bail!("Expected the note to be built, got {status:?}"),
+            }
+
+            // Once most guardians disagree with the requested time, it's rejected
+            let agreed = request(&stale).created_at + 2 * tolerance;
+            for peer in 0..3 {
+                module
+                    .process_note_request(&mut dbtx, request(&stale), agreed, PeerId::from(peer))
+                    .await?;
+            }
+            match dbtx
+                .get_value(&NostimintNoteRequestKey(request(&stale)))
+                .await
+            {
+                Some(NoteStatus::Rejected(reason)) => assert!(reason.contains("Stale")),
+                status => anyhow::bail!("Expected the note to be rejected, got {status:?}"),
+            }
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expires_unsigned_notes() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let key = account_key();
+        let account = key.x_only_public_key().0;
+        let expiry = module.cfg.consensus.expiry_sessions;
+        let (pending, signing) = (
+            text_note(&module, &key, "pending"),
+            text_note(&module, &key, "signing"),
+        );
+        let request = |input: &NostimintInput| input.note.clone().expect("pays for a note").request;
+        let funds = Amount::from_sats(10);
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &funds)
+                .await;
+            for input in [&pending, &signing] {
+                module
+                    .process_input(&mut dbtx, input, &NostimintVerificationCache)
+                    .await
+                    .expect("account paid for the note");
+            }
+            module
+                .process_note_request(&mut dbtx, request(&signing), unix_now(), PeerId::from(0))
+                .await?;
+            let Some(NoteStatus::Signing(event)) = dbtx
+                .get_value(&NostimintNoteRequestKey(request(&signing)))
+                .await
+            else {
+                anyhow::bail!("Expected the note to be built");
+            };
+
+            // Nothing expires before its sessions are up
+            module.expire_notes(&mut dbtx, expiry - 1).await;
+            assert_eq!(
+                dbtx.get_value(&NostimintNoteRequestKey(request(&pending)))
+                    .await,
+                Some(NoteStatus::Pending)
+            );
+
+            // Expired notes leave a tombstone and are refunded, a built note
+            // can no longer be signed
+            module.expire_notes(&mut dbtx, expiry).await;
+            for input in [&pending, &signing] {
+                assert_eq!(
+                    dbtx.get_value(&NostimintNoteRequestKey(request(input)))
+                        .await,
+                    Some(NoteStatus::Expired)
+                );
+            }
+            assert_eq!(dbtx.get_value(&NostimintEventKey(event)).await, None);
+            assert_eq!(
+                dbtx.get_value(&NostimintFundsKeyV1(account)).await,
+                Some(funds - module.cfg.consensus.tx_fee - module.cfg.consensus.tx_fee)
+            );
+            assert_eq!(
+                dbtx.get_value(&NostimintGcStatsKey).await,
+                Some(GcStats {
+                    session: expiry,
+                    expired_notes: 2,
+                })
+            );
+
+            // Tombstones are dropped after another expiry
+            module.expire_notes(&mut dbtx, 2 * expiry).await;
+            for input in [&pending, &signing] {
+                assert_eq!(
+                    dbtx.get_value(&NostimintNoteRequestKey(request(input)))
+                        .await,
+                    None
+                );
+            }
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refunds_fee_and_quota_of_rejected_notes() -> anyhow::Result<()> {
+        let mut module = single_guardian();
+        module.cfg.consensus.note_quota.max_notes = 1;
+        let db = memory_db();
+        let key = account_key();
+        let account = key.x_only_public_key().0;
+        let (vetoed, next) = (
+            text_note(&module, &key, "vetoed"),
+            text_note(&module, &key, "next"),
+        );
+        let funds = Amount::from_sats(10);
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &funds)
+                .await;
+            module
+                .process_input(&mut dbtx, &vetoed, &NostimintVerificationCache)
+                .await
+                .expect("quota is unused");
+        }
+        dbtx.commit_tx().await;
+
+        // The tx paying for another note fails and is rolled back
+        let mut dbtx = db.begin_transaction().await;
+        let error = module
+            .process_input(
+                &mut dbtx.with_module_prefix(0),
+                &next,
+                &NostimintVerificationCache,
+            )
+            .await
+            .expect_err("quota is used up");
+        assert!(format!("{error:?}").contains(&NostimintError::QuotaExceeded(1).to_string()));
+        drop(dbtx);
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            let request = vetoed.note.clone().expect("input pays for a note").request;
+            module
+                .process_note_veto(&mut dbtx, request, "No".to_string(), PeerId::from(0))
+                .await?;
+
+            // Only the tx fee is kept, the quota slot can be used again
+            assert_eq!(
+                dbtx.get_value(&NostimintFundsKeyV1(account)).await,
+                Some(funds - module.cfg.consensus.tx_fee)
+            );
+            module
+                .process_input(&mut dbtx, &next, &NostimintVerificationCache)
+                .await
+                .expect("quota slot was refunded");
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lists_relay_receipts_per_note() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let receipt = |relay: &str, accepted| RelayReceipt {
+            peer: PeerId::from(0),
+            relay: relay.to_string(),
+            accepted,
+            message: String::new(),
+            timestamp: unix_now(),
+        };
+        let note = NoteId(EventId::from_slice(&[0; 32])?);
+        let other = NoteId(EventId::from_slice(&[1; 32])?);
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            assert!(module.note_receipts(&mut dbtx, note).await.is_empty());
+
+            let receipts = vec![
+                receipt("wss://a.example", true),
+                receipt("wss://b.example", false),
+            ];
+            for receipt in &receipts {
+                dbtx.insert_new_entry(&NostimintReceiptKey(note, receipt.relay.clone()), receipt)
+                    .await;
+            }
+            let elsewhere = receipt("wss://a.example", true);
+            dbtx.insert_new_entry(
+                &NostimintReceiptKey(other, elsewhere.relay.clone()),
+                &elsewhere,
+            )
+            .await;
+
+            assert_eq!(module.note_receipts(&mut dbtx, note).await, receipts);
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tracks_balances_and_audits_them() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let account = account_key().x_only_public_key().0;
+        let out_point = OutPoint {
+            txid: TransactionId::all_zeros(),
+            out_idx: 0,
+        };
+        let deposit = NostimintOutput {
+            amount: Amount::from_sats(21),
+            account,
+            zap: None,
+        };
+        let withdrawal = NostimintInput {
+            amount: Amount::from_sats(5),
+            account,
+            note: None,
+            name: None,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            module
+                .process_output(&mut dbtx, &deposit, out_point)
+                .await
+                .expect("deposits are always accepted");
+            assert_eq!(
+                module.output_status(&mut dbtx, out_point).await,
+                Some(NostimintOutputOutcome(Amount::from_sats(21), account))
+            );
+
+            module
+                .process_input(&mut dbtx, &withdrawal, &NostimintVerificationCache)
+                .await
+                .expect("account has the funds");
+            assert_eq!(
+                dbtx.get_value(&NostimintFundsKeyV1(account)).await,
+                Some(Amount::from_sats(16))
+            );
+
+            // The funds owed to the account are the module's liability
+            let mut audit = Audit::default();
+            module.audit(&mut dbtx, &mut audit).await;
+            assert_eq!(audit.sum().milli_sat, -(Amount::from_sats(16).msats as i64));
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prints_money_only_with_the_issuer_key() -> anyhow::Result<()> {
+        let mut module = single_guardian();
+        let db = memory_db();
+        let issuer = account_key().x_only_public_key().0;
+        let print = NostimintInput {
+            amount: Amount::from_sats(1500),
+            account: issuer,
+            note: None,
+            name: None,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+
+            // Without an issuer key configured, it's an account like any other
+            let error = module
+                .process_input(&mut dbtx, &print, &NostimintVerificationCache)
+                .await
+                .expect_err("printing money is disabled");
+            assert!(format!("{error:?}").contains(&NostimintError::NotEnoughFunds.to_string()));
+
+            // Printed money is backed by nothing, so it's counted as an asset
+            module.cfg.consensus.issuer_key = Some(issuer);
+            module
+                .process_input(&mut dbtx, &print, &NostimintVerificationCache)
+                .await
+                .expect("the issuer prints money");
+            let mut audit = Audit::default();
+            module.audit(&mut dbtx, &mut audit).await;
+            assert_eq!(audit.sum().milli_sat, Amount::from_sats(1500).msats as i64);
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transfers_between_accounts() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let alice = account_key().x_only_public_key().0;
+        let bob = account_key().x_only_public_key().0;
+        let transfer = |amount| {
+            (
+                NostimintInput {
+                    amount,
+                    account: alice,
+                    note: None,
+                    name: None,
+                },
+                NostimintOutput {
+                    amount,
+                    account: bob,
+                    zap: None,
+                },
+            )
+        };
+        let out_point = OutPoint {
+            txid: TransactionId::all_zeros(),
+            out_idx: 0,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            dbtx.insert_new_entry(&NostimintFundsKeyV1(alice), &Amount::from_sats(10))
+                .await;
+
+            let (input, output) = transfer(Amount::from_sats(7));
+            module
+                .process_input(&mut dbtx, &input, &NostimintVerificationCache)
+                .await
+                .expect("alice has the funds");
+            module
+                .process_output(&mut dbtx, &output, out_point)
+                .await
+                .expect("bob can be paid");
+            assert_eq!(
+                dbtx.get_value(&NostimintFundsKeyV1(alice)).await,
+                Some(Amount::from_sats(3))
+            );
+            assert_eq!(
+                module.output_status(&mut dbtx, out_point).await,
+                Some(NostimintOutputOutcome(Amount::from_sats(7), bob))
+            );
+
+            // Alice can't send more than she has left
+            let (input, _) = transfer(Amount::from_sats(7));
+            let error = module
+                .process_input(&mut dbtx, &input, &NostimintVerificationCache)
+                .await
+                .expect_err("alice lacks the funds");
+            assert!(format!("{error:?}").contains(&NostimintError::NotEnoughFunds.to_string()));
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signs_zap_receipts_at_the_agreed_time() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let recipient = Keys::generate().public_key();
+        let account = XOnlyPublicKey::from_slice(&recipient.serialize())?;
+        let tags = vec![Tag::parse(vec!["p".to_string(), recipient.to_string()])?];
+        let zap_request = Event {
+            event: EventBuilder::new(Kind::from(kinds::ZAP_REQUEST), "", &tags)
+                .to_event(&Keys::generate())?,
+        };
+        let amount = Amount::from_sats(21);
+        let receipt = kinds::zap_receipt(&zap_request, account, amount)?;
+        let output = NostimintOutput {
+            amount,
+            account,
+            zap: Some(zap_request),
+        };
+        let out_point = OutPoint {
+            txid: TransactionId::all_zeros(),
+            out_idx: 0,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            module
+                .process_output(&mut dbtx, &output, out_point)
+                .await
+                .expect("zap request is for the account paid");
+
+            // Way past the tolerance for user notes, the zap was paid already
+            let agreed = receipt.created_at + 10 * module.cfg.consensus.timestamp_tolerance;
+            module
+                .process_note_request(&mut dbtx, receipt.clone(), agreed, PeerId::from(0))
+                .await?;
+            match dbtx.get_value(&NostimintNoteRequestKey(receipt)).await {
+                Some(NoteStatus::Signing(event)) => {
+                    assert_eq!(event.event.created_at.as_u64(), agreed)
+                }
+                status => anyhow::bail!("Expected the receipt to be signed, got {status:?}"),
+            }
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn registers_each_name_once() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let alice = XOnlyPublicKey::from_slice(&Keys::generate().public_key().serialize())?;
+        let bob = XOnlyPublicKey::from_slice(&Keys::generate().public_key().serialize())?;
+        let claim = |account| NostimintInput {
+            amount: module.cfg.consensus.name_fee + module.cfg.consensus.tx_fee,
+            account,
+            note: None,
+            name: Some("alice".to_string()),
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            for account in [alice, bob] {
+                dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::from_sats(10))
+                    .await;
+            }
+            module
+                .process_input(&mut dbtx, &claim(alice), &NostimintVerificationCache)
+                .await
+                .expect("name is free");
+
+            // The first claim wins, even if another account pays for the name
+            let error = module
+                .process_input(&mut dbtx, &claim(bob), &NostimintVerificationCache)
+                .await
+                .expect_err("name is taken");
+            assert!(format!("{error:?}").contains(&NostimintError::NameTaken.to_string()));
+
+            // `.well-known/nostr.json` maps each name to the hex key of its account
+            let document = module.nostr_json(&mut dbtx, None).await;
+            assert_eq!(
+                document.names,
+                BTreeMap::from([("alice".to_string(), alice)])
+            );
+            assert_eq!(
+                serde_json::to_value(&document)?,
+                serde_json::json!({ "names": { "alice": alice.to_string() } })
+            );
+            let document = module
+                .nostr_json(&mut dbtx, Some("alice".to_string()))
+                .await;
+            assert_eq!(document.names.get("alice"), Some(&alice));
+            let document = module.nostr_json(&mut dbtx, Some("bob".to_string())).await;
+            assert!(document.names.is_empty());
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keeps_replaceable_events_apart_per_account() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let (alice_key, bob_key) = (account_key(), account_key());
+        let (alice, bob) = (
+            alice_key.x_only_public_key().0,
+            bob_key.x_only_public_key().0,
+        );
+        let friend = Keys::generate().public_key().to_string();
+        let sign = |key: &KeyPair, kind, content: &str, tags: Vec<Vec<String>>| NostimintInput {
+            amount: module.cfg.consensus.note_fee + module.cfg.consensus.tx_fee,
+            account: key.x_only_public_key().0,
+            note: Some(
+                NoteRequest {
+                    kind,
+                    content: content.to_string(),
+                    tags,
+                    created_at: 1_700_000_000,
+                }
+                .sign(key),
+            ),
+            name: None,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            for account in [alice, bob] {
+                dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::from_sats(10))
+                    .await;
+            }
+
+            // Each account's articles live under its own `d` tags
+            for key in [&alice_key, &bob_key] {
+                let account = key.x_only_public_key().0;
+                let tags = kinds::namespace_identifiers(
+                    kinds::LONG_FORM,
+                    vec![vec!["d".to_string(), "intro".to_string()]],
+                    account,
+                );
+                module
+                    .process_input(
+                        &mut dbtx,
+                        &sign(key, kinds::LONG_FORM, "hello", tags),
+                        &NostimintVerificationCache,
+                    )
+                    .await
+                    .expect("article is namespaced to the account");
+            }
+            let unprefixed = vec![vec!["d".to_string(), "intro".to_string()]];
+            module
+                .process_input(
+                    &mut dbtx,
+                    &sign(&alice_key, kinds::LONG_FORM, "again", unprefixed),
+                    &NostimintVerificationCache,
+                )
+                .await
+                .expect_err("article would replace any account's");
+
+            // The first account to get a contact list signed owns it
+            let follows = vec![vec!["p".to_string(), friend]];
+            module
+                .process_input(
+                    &mut dbtx,
+                    &sign(&alice_key, kinds::CONTACT_LIST, "", follows.clone()),
+                    &NostimintVerificationCache,
+                )
+                .await
+                .expect("contact list is unclaimed");
+            let error = module
+                .process_input(
+                    &mut dbtx,
+                    &sign(&bob_key, kinds::CONTACT_LIST, "", follows.clone()),
+                    &NostimintVerificationCache,
+                )
+                .await
+                .expect_err("contact list belongs to alice");
+            assert!(format!("{error:?}")
+                .contains(&NostimintError::KindTaken(kinds::CONTACT_LIST).to_string()));
+            module
+                .process_input(
+                    &mut dbtx,
+                    &sign(&alice_key, kinds::CONTACT_LIST, "updated", follows),
+                    &NostimintVerificationCache,
+                )
+                .await
+                .expect("alice can replace her own contact list");
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn excludes_vetoing_guardians_from_signing() -> anyhow::Result<()> {
+        let module = guardians(3, 2).remove(0);
+        let db = memory_db();
+        let key = account_key();
+        let account = key.x_only_public_key().0;
+        let request = |content: &str| NoteRequest {
+            kind: kinds::TEXT_NOTE,
+            content: content.to_string(),
+            tags: vec![],
+            created_at: unix_now(),
+        };
+        let (signed, vetoed) = (request("signed"), request("vetoed"));
+        let (ours, theirs, vetoer) = (PeerId::from(0), PeerId::from(1), PeerId::from(2));
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::from_sats(10))
+                .await;
+            for request in [&signed, &vetoed] {
+                let input = NostimintInput {
+                    amount: module.cfg.consensus.note_fee + module.cfg.consensus.tx_fee,
+                    account,
+                    note: Some(request.clone().sign(&key)),
+                    name: None,
+                };
+                module
+                    .process_input(&mut dbtx, &input, &NostimintVerificationCache)
+                    .await
+                    .expect("account paid for the note");
+            }
+
+            // A threshold approves the note even though one guardian vetoed it
+            let veto = "Not on my watch".to_string();
+            module
+                .process_note_veto(&mut dbtx, signed.clone(), veto.clone(), vetoer)
+                .await?;
+            for peer in [ours, theirs] {
+                module
+                    .process_note_request(&mut dbtx, signed.clone(), unix_now(), peer)
+                    .await?;
+            }
+            let Some(NoteStatus::Signing(event)) =
+                dbtx.get_value(&NostimintNoteRequestKey(signed)).await
+            else {
+                anyhow::bail!("Expected the note to be built");
+            };
+
+            // The vetoing guardian can't join the signing set, the others can
+            let commitment = SecretNonce::random().commitment();
+            assert!(module
+                .process_nonce(&mut dbtx, event.clone(), commitment.clone(), vetoer)
+                .await
+                .is_err());
+            module
+                .process_nonce(&mut dbtx, event, commitment, ours)
+                .await?;
+
+            // Once too many guardians veto for a threshold to approve, it's rejected
+            for peer in [theirs, vetoer] {
+                module
+                    .process_note_veto(&mut dbtx, vetoed.clone(), veto.clone(), peer)
+                    .await?;
+            }
+            match dbtx.get_value(&NostimintNoteRequestKey(vetoed)).await {
+                Some(NoteStatus::Rejected(reason)) => assert!(reason.contains(&veto)),
+                status => anyhow::bail!("Expected the note to be rejected, got {status:?}"),
+            }
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn queues_notes_signed_by_their_account() -> anyhow::Result<()> {
+        let module = single_guardian();
+        let db = memory_db();
+        let key = account_key();
+        let account = key.x_only_public_key().0;
+        let request = NoteRequest {
+            kind: kinds::TEXT_NOTE,
+            content: "signed by me".to_string(),
+            tags: vec![],
+            created_at: unix_now(),
+        };
+        let input = |note| NostimintInput {
+            amount: module.cfg.consensus.note_fee + module.cfg.consensus.tx_fee,
+            account,
+            note: Some(note),
+            name: None,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::from_sats(10))
+                .await;
+
+            // Paying from the account isn't enough, it has to sign the request
+            let forged = request.clone().sign(&account_key());
+            module
+                .process_input(&mut dbtx, &input(forged), &NostimintVerificationCache)
+                .await
+                .expect_err("request is signed by another key");
+            assert_eq!(
+                dbtx.get_value(&NostimintNoteRequestKey(request.clone()))
+                    .await,
+                None
+            );
+
+            module
+                .process_input(
+                    &mut dbtx,
+                    &input(request.clone().sign(&key)),
+                    &NostimintVerificationCache,
+                )
+                .await
+                .expect("request is signed by the account");
+            assert_eq!(
+                dbtx.get_value(&NostimintRequesterKey(request)).await,
+                Some(account)
+            );
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn counts_quota_in_agreed_sessions() -> anyhow::Result<()> {
+        let mut module = single_guardian();
+        module.cfg.consensus.note_quota.max_notes = 1;
+        let db = memory_db();
+        let key = account_key();
+        let account = key.x_only_public_key().0;
+        let window = module.cfg.consensus.note_quota.window;
+        let note = |created_at| NostimintInput {
+            amount: module.cfg.consensus.note_fee + module.cfg.consensus.tx_fee,
+            account,
+            note: Some(
+                NoteRequest {
+                    kind: kinds::TEXT_NOTE,
+                    content: format!("requested at {created_at}"),
+                    tags: vec![],
+                    created_at,
+                }
+                .sign(&key),
+            ),
+            name: None,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut dbtx = dbtx.with_module_prefix(0);
+            dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::from_sats(10))
+                .await;
+            module
+                .process_input(&mut dbtx, &note(window - 1), &NostimintVerificationCache)
+                .await
+                .expect("quota is unused");
+
+            // Picking a time in the next window doesn't get a fresh quota
+            let error = module
+                .process_input(&mut dbtx, &note(window), &NostimintVerificationCache)
+                .await
+                .expect_err("quota of the session's window is used up");
+            assert!(format!("{error:?}").contains(&NostimintError::QuotaExceeded(1).to_string()));
+
+            // Once the federation's sessions reach the next window it does
+            let next_window = window / module.cfg.consensus.session_length;
+            dbtx.insert_entry(&NostimintSessionKey, &next_window).await;
+            module
+                .process_input(&mut dbtx, &note(window + 1), &NostimintVerificationCache)
+                .await
+                .expect("quota of the next window is unused");
+        }
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt::Debug;
+
+use fedimint_nostimint_common::config::NotePolicyConfig;
+use fedimint_nostimint_common::NoteRequest;
+use secp256k1::XOnlyPublicKey;
+
+/// Lets a guardian refuse to sign notes, a note is only signed if a threshold
+/// of guardians accept it
+pub trait NotePolicy: Debug + Send + Sync {
+    /// Returns why the guardian vetoes the note, `None` to accept it
+    fn veto(&self, request: &NoteRequest, requester: Option<XOnlyPublicKey>) -> Option<String>;
+}
+
+/// The policy guardians configure in their local config
+impl NotePolicy for NotePolicyConfig {
+    fn veto(&self, request: &NoteRequest, requester: Option<XOnlyPublicKey>) -> Option<String> {
+        if let Some(requester) = requester {
+            if self.denied_requesters.contains(&requester) {
+                return Some(format!("Requester {requester} is denied"));
+            }
+            if !self.allowed_requesters.is_empty() && !self.allowed_requesters.contains(&requester)
+            {
+                return Some(format!("Requester {requester} is not allowed"));
+            }
+        }
+
+        if let Some(allowed_kinds) = &self.allowed_kinds {
+            if !allowed_kinds.contains(&request.kind) {
+                return Some(format!("Kind {} is not allowed", request.kind));
+            }
+        }
+
+        if let Some(max_content_len) = self.max_content_len {
+            if request.content.len() as u64 > max_content_len {
+                return Some(format!("Content is longer than {max_content_len} bytes"));
+            }
+        }
+
+        if let Some(max_tags) = self.max_tags {
+            if request.tags.len() as u64 > max_tags {
+                return Some(format!("Note has more than {max_tags} tags"));
+            }
+        }
+
+        let content = request.content.to_lowercase();
+        self.banned_words
+            .iter()
+            .find(|word| content.contains(&word.to_lowercase()))
+            .map(|word| format!("Content contains banned word {word:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_nostimint_common::kinds;
+
+    use super::*;
+
+    fn note(content: &str) -> NoteRequest {
+        NoteRequest {
+            kind: kinds::TEXT_NOTE,
+            content: content.to_string(),
+            tags: vec![vec!["t".to_string(), "nostr".to_string()]],
+            created_at: 1_700_000_000,
+        }
+    }
+
+    fn account(byte: u8) -> XOnlyPublicKey {
+        let secp = secp256k1::Secp256k1::new();
+        let key = secp256k1::SecretKey::from_slice(&[byte; 32]).expect("valid secret key");
+        key.x_only_public_key(&secp).0
+    }
+
+    #[test]
+    fn approves_notes_within_the_policy() {
+        let policy = NotePolicyConfig {
+            max_content_len: Some(100),
+            max_tags: Some(1),
+            banned_words: vec!["spam".to_string()],
+            allowed_kinds: Some(BTreeSet::from([kinds::TEXT_NOTE])),
+            allowed_requesters: BTreeSet::from([account(1)]),
+            denied_requesters: BTreeSet::new(),
+        };
+        assert_eq!(policy.veto(&note("hello"), Some(account(1))), None);
+        // Profiles and zap receipts have no requester to check
+        assert_eq!(policy.veto(&note("hello"), None), None);
+        assert_eq!(NotePolicyConfig::default().veto(&note("spam"), None), None);
+    }
+
+    #[test]
+    fn vetoes_notes_against_the_policy() {
+        let policy = NotePolicyConfig {
+            max_content_len: Some(10),
+            max_tags: Some(0),
+            banned_words: vec!["Spam".to_string()],
+            allowed_kinds: Some(BTreeSet::from([kinds::REACTION])),
+            allowed_requesters: BTreeSet::new(),
+            denied_requesters: BTreeSet::from([account(2)]),
+        };
+        let vetoed = |policy: &NotePolicyConfig, request: &NoteRequest| {
+            policy.veto(request, Some(account(1))).is_some()
+        };
+        let allowed = NotePolicyConfig::default();
+
+        assert!(policy.veto(&note("hi"), Some(account(2))).is_some());
+        assert!(vetoed(&policy, &note("hi")));
+        let only_reactions = NotePolicyConfig {
+            allowed_kinds: policy.allowed_kinds.clone(),
+            ..allowed.clone()
+        };
+        assert!(vetoed(&only_reactions, &note("hi")));
+        let short = NotePolicyConfig {
+            max_content_len: Some(10),
+            ..allowed.clone()
+        };
+        assert!(vetoed(&short, &note("a much longer note")));
+        let untagged = NotePolicyConfig {
+            max_tags: Some(0),
+            ..allowed.clone()
+        };
+        assert!(vetoed(&untagged, &note("hi")));
+        let clean = NotePolicyConfig {
+            banned_words: policy.banned_words.clone(),
+            ..allowed.clone()
+        };
+        assert!(vetoed(&clean, &note("buy SPAM now")));
+        let invited = NotePolicyConfig {
+            allowed_requesters: BTreeSet::from([account(3)]),
+            ..allowed
+        };
+        assert!(vetoed(&invited, &note("hi")));
+    }
+}
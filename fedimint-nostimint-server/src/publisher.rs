@@ -0,0 +1,409 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use fedimint_core::db::Database;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::task::{sleep, timeout, TaskHandle};
+use fedimint_core::PeerId;
+use fedimint_nostimint_common::{Event, NoteId, RelayReceipt};
+use futures::{SinkExt, StreamExt};
+use nostr_sdk::{ClientMessage, RelayMessage};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use crate::db::{
+    NostimintDeliveryKey, NostimintPublisherCursorKey, NostimintReceiptKey, NostimintSignedNoteKey,
+    NostimintUndeliveredKey, NostimintUndeliveredPrefix,
+};
+use crate::unix_now;
+
+/// How long we wait for a relay to answer an event
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long we wait before retrying relays that couldn't be reached
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What a relay answered to an event we published
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RelayResponse {
+    /// Whether the relay stored the event
+    pub accepted: bool,
+    /// The reason the relay gave in its `OK` message
+    pub message: String,
+    /// `NOTICE` messages the relay sent before answering
+    pub notices: Vec<String>,
+}
+
+/// Attempts to deliver a signed note to a single relay, once the relay
+/// answered its `OK` is kept as a [`RelayReceipt`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct DeliveryStatus {
+    /// How often we tried to reach the relay
+    pub attempts: u32,
+    /// `NOTICE` messages the relay sent us
+    pub notices: Vec<String>,
+    /// Why the last attempt failed, if it did
+    pub last_error: Option<String>,
+}
+
+/// Publishes an event to a relay, returning once the relay answered with `OK`
+pub async fn publish_event(relay: &str, event: &Event) -> anyhow::Result<RelayResponse> {
+    timeout(RELAY_TIMEOUT, publish_event_inner(relay, event))
+        .await
+        .map_err(|_| anyhow!("Relay did not answer in time"))?
+}
+
+async fn publish_event_inner(relay: &str, event: &Event) -> anyhow::Result<RelayResponse> {
+    let (mut socket, _) = connect_async(relay).await?;
+    let message = ClientMessage::new_event(event.event.clone()).as_json();
+    socket.send(Message::Text(message)).await?;
+
+    let mut notices = vec![];
+    while let Some(message) = socket.next().await {
+        let text = match message? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        match RelayMessage::from_json(&text) {
+            Ok(RelayMessage::Ok {
+                event_id,
+                status,
+                message,
+            }) if event_id == event.event.id => {
+                let _ = socket.close(None).await;
+                return Ok(RelayResponse {
+                    accepted: status,
+                    message,
+                    notices,
+                });
+            }
+            Ok(RelayMessage::Notice { message }) => notices.push(message),
+            Ok(_) => {}
+            Err(e) => debug!(relay, "Ignoring unknown relay message: {e}"),
+        }
+    }
+
+    bail!("Relay closed the connection without answering")
+}
+
+/// Background task pushing every signed note to the relays of our local config
+#[derive(Debug)]
+pub struct RelayPublisher {
+    pub db: Database,
+    /// Our id, relays answer to each guardian separately
+    pub our_peer_id: PeerId,
+    pub relays: Vec<String>,
+    pub max_attempts: u32,
+}
+
+impl RelayPublisher {
+    /// Publishes pending notes until the task group shuts down
+    pub async fn run(self, handle: TaskHandle) {
+        info!(relays = ?self.relays, "Starting relay publisher");
+        while !handle.is_shutting_down() {
+            let next = self.queue_signed_notes().await;
+            self.publish_pending().await;
+
+            // Wake up once the next note got signed, or to retry unreachable relays
+            tokio::select! {
+                _ = self.db.wait_key_exists(&NostimintSignedNoteKey(next)) => {}
+                _ = sleep(RETRY_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Queues the notes signed since we last looked for delivery, returning
+    /// the position the next signed note will get
+    ///
+    /// Only we write the queue and our cursor, consensus just appends to the
+    /// signed notes, so this never conflicts with it.
+    pub async fn queue_signed_notes(&self) -> u64 {
+        let mut dbtx = self.db.begin_transaction().await;
+        let mut next = dbtx
+            .get_value(&NostimintPublisherCursorKey)
+            .await
+            .unwrap_or(0);
+        while let Some(event) = dbtx.get_value(&NostimintSignedNoteKey(next)).await {
+            // Without relays there is nowhere to deliver the note to
+            if !self.relays.is_empty() {
+                dbtx.insert_entry(&NostimintUndeliveredKey(NoteId(event.event.id)), &event)
+                    .await;
+            }
+            next += 1;
+        }
+        dbtx.insert_entry(&NostimintPublisherCursorKey, &next).await;
+        dbtx.commit_tx().await;
+        next
+    }
+
+    /// Tries to deliver each undelivered note to every relay that hasn't
+    /// answered yet, dropping notes from the queue once we are done with them
+    pub async fn publish_pending(&self) {
+        let mut dbtx = self.db.begin_transaction().await;
+        let undelivered: Vec<Event> = dbtx
+            .find_by_prefix(&NostimintUndeliveredPrefix)
+            .await
+            .map(|(_, event)| event)
+            .collect()
+            .await;
+        dbtx.commit_tx().await;
+
+        for event in undelivered {
+            let mut done = true;
+            for relay in &self.relays {
+                done &= self.deliver(&event, relay).await;
+            }
+
+            if done {
+                let mut dbtx = self.db.begin_transaction().await;
+                dbtx.remove_entry(&NostimintUndeliveredKey(NoteId(event.event.id)))
+                    .await;
+                dbtx.commit_tx().await;
+            }
+        }
+    }
+
+    /// Makes an attempt to deliver a note to a relay, returning whether we are
+    /// done with it since the relay answered or we gave up on it
+    async fn deliver(&self, event: &Event, relay: &str) -> bool {
+        let id = NoteId(event.event.id);
+        let key = NostimintDeliveryKey(id, relay.to_string());
+        let receipt_key = NostimintReceiptKey(id, relay.to_string());
+        let mut dbtx = self.db.begin_transaction().await;
+        let status = dbtx.get_value(&key).await;
+        let answered = dbtx.get_value(&receipt_key).await.is_some();
+        dbtx.commit_tx().await;
+
+        let mut status = status.unwrap_or(DeliveryStatus {
+            attempts: 0,
+            notices: vec![],
+            last_error: None,
+        });
+        // We are done once the relay answered or we gave up on it
+        if answered || status.attempts >= self.max_attempts {
+            return true;
+        }
+
+        status.attempts += 1;
+        let mut receipt = None;
+        match publish_event(relay, event).await {
+            Ok(response) => {
+                if !response.accepted {
+                    warn!(relay, event_id = %event.event.id, message = %response.message, "Relay rejected note");
+                }
+                status.notices.extend(response.notices);
+                status.last_error = None;
+                receipt = Some(RelayReceipt {
+                    peer: self.our_peer_id,
+                    relay: relay.to_string(),
+                    accepted: response.accepted,
+                    message: response.message,
+                    timestamp: unix_now(),
+                });
+            }
+            Err(e) => {
+                warn!(relay, event_id = %event.event.id, attempt = status.attempts, "Failed to publish note: {e}");
+                status.last_error = Some(e.to_string());
+            }
+        }
+
+        // Only we write delivery entries, so the network round trip can't race
+        let done = receipt.is_some() || status.attempts >= self.max_attempts;
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&key, &status).await;
+        if let Some(receipt) = receipt {
+            dbtx.insert_entry(&receipt_key, &receipt).await;
+        }
+        dbtx.commit_tx().await;
+        done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{EventBuilder, Keys};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+    use crate::tests::memory_db;
+
+    #[tokio::test]
+    async fn publishes_to_mock_relay() -> anyhow::Result<()> {
+        let event = EventBuilder::new_text_note("hello", &[]).to_event(&Keys::generate())?;
+        let event = Event { event };
+        let relay = mock_relay(vec![
+            RelayMessage::new_notice("slow down"),
+            RelayMessage::new_ok(event.event.id, true, ""),
+        ])
+        .await?;
+
+        let response = publish_event(&relay, &event).await?;
+        assert!(response.accepted);
+        assert_eq!(response.notices, vec!["slow down".to_string()]);
+
+        let rejecting = mock_relay(vec![RelayMessage::new_ok(
+            event.event.id,
+            false,
+            "blocked: not on allowlist",
+        )])
+        .await?;
+        let response = publish_event(&rejecting, &event).await?;
+        assert!(!response.accepted);
+        assert_eq!(response.message, "blocked: not on allowlist");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_relays_until_they_answer_or_we_give_up() -> anyhow::Result<()> {
+        let db = memory_db();
+        let event = EventBuilder::new_text_note("hello", &[]).to_event(&Keys::generate())?;
+        let event = Event { event };
+        let id = NoteId(event.event.id);
+        let answering = mock_relay(vec![
+            RelayMessage::new_notice("welcome"),
+            RelayMessage::new_ok(event.event.id, true, ""),
+        ])
+        .await?;
+        let unreachable = unreachable_relay().await?;
+        let publisher = RelayPublisher {
+            db: db.clone(),
+            our_peer_id: PeerId::from(0),
+            relays: vec![answering.clone(), unreachable.clone()],
+            max_attempts: 2,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(&NostimintSignedNoteKey(0), &event)
+            .await;
+        dbtx.commit_tx().await;
+        assert_eq!(publisher.queue_signed_notes().await, 1);
+
+        // The answer is kept, the unreachable relay is tried again
+        publisher.publish_pending().await;
+        let mut dbtx = db.begin_transaction().await;
+        let receipt = dbtx
+            .get_value(&NostimintReceiptKey(id, answering.clone()))
+            .await
+            .expect("relay answered");
+        assert!(receipt.accepted);
+        assert_eq!(
+            dbtx.get_value(&NostimintDeliveryKey(id, answering.clone()))
+                .await,
+            Some(DeliveryStatus {
+                attempts: 1,
+                notices: vec!["welcome".to_string()],
+                last_error: None,
+            })
+        );
+        let status = dbtx
+            .get_value(&NostimintDeliveryKey(id, unreachable.clone()))
+            .await
+            .expect("delivery was attempted");
+        assert_eq!(status.attempts, 1);
+        assert!(status.last_error.is_some());
+        assert!(dbtx.get_value(&NostimintUndeliveredKey(id)).await.is_some());
+        dbtx.commit_tx().await;
+
+        // After the last attempt the note leaves the queue, the relay that
+        // answered isn't contacted again
+        publisher.publish_pending().await;
+        publisher.publish_pending().await;
+        let mut dbtx = db.begin_transaction().await;
+        let status = dbtx
+            .get_value(&NostimintDeliveryKey(id, unreachable.clone()))
+            .await
+            .expect("delivery was attempted");
+        assert_eq!(status.attempts, 2);
+        assert_eq!(
+            dbtx.get_value(&NostimintDeliveryKey(id, answering))
+                .await
+                .map(|status| status.attempts),
+            Some(1)
+        );
+        assert!(dbtx.get_value(&NostimintUndeliveredKey(id)).await.is_none());
+        assert!(dbtx
+            .get_value(&NostimintReceiptKey(id, unreachable))
+            .await
+            .is_none());
+        dbtx.commit_tx().await;
+
+        // Notes are only queued once
+        assert_eq!(publisher.queue_signed_notes().await, 1);
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.find_by_prefix(&NostimintUndeliveredPrefix)
+                .await
+                .count()
+                .await,
+            0
+        );
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn queues_nothing_without_relays() -> anyhow::Result<()> {
+        let db = memory_db();
+        let event = EventBuilder::new_text_note("hello", &[]).to_event(&Keys::generate())?;
+        let publisher = RelayPublisher {
+            db: db.clone(),
+            our_peer_id: PeerId::from(0),
+            relays: vec![],
+            max_attempts: 2,
+        };
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(&NostimintSignedNoteKey(0), &Event { event })
+            .await;
+        dbtx.commit_tx().await;
+        assert_eq!(publisher.queue_signed_notes().await, 1);
+
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.find_by_prefix(&NostimintUndeliveredPrefix)
+                .await
+                .count()
+                .await,
+            0
+        );
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    /// Serves a single connection, answering the first event with `replies`
+    async fn mock_relay(replies: Vec<RelayMessage>) -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}", listener.local_addr()?);
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("client connects");
+            let mut socket = accept_async(stream).await.expect("websocket handshake");
+            while let Some(Ok(Message::Text(text))) = socket.next().await {
+                if let Ok(ClientMessage::Event(_)) = ClientMessage::from_json(&text) {
+                    for reply in &replies {
+                        socket
+                            .send(Message::Text(reply.as_json()))
+                            .await
+                            .expect("client listens");
+                    }
+                }
+            }
+        });
+
+        Ok(url)
+    }
+
+    /// The address of a relay that refuses connections
+    async fn unreachable_relay() -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        Ok(format!("ws://{}", listener.local_addr()?))
+    }
+}
@@ -0,0 +1,697 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, ensure};
+use fedimint_core::db::{Database, DatabaseTransaction};
+use fedimint_core::task::{sleep, TaskHandle};
+use fedimint_nostimint_common::{Event, NoteId, MAX_EVENT_SIZE};
+use futures::stream::{self, BoxStream};
+use futures::{SinkExt, StreamExt};
+use nostr_sdk::{ClientMessage, EventId, Filter, RelayMessage, SubscriptionId};
+use secp256k1::XOnlyPublicKey;
+use serde::Deserialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinSet;
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use crate::db::{
+    NostimintFundsKeyV1, NostimintRelayAuthorCountKey, NostimintRelayAuthorEventsPrefix,
+    NostimintRelayAuthorKey, NostimintRelayBytesKey, NostimintRelayCursorKey,
+    NostimintRelayEventKey, NostimintRelayKindEventsPrefix, NostimintRelayKindKey,
+    NostimintRelayTimeKey, NostimintRelayTimePrefix, NostimintSignedNoteKey,
+};
+
+/// How many live events a slow connection can fall behind before it misses some
+const LIVE_EVENTS_CAPACITY: usize = 1024;
+
+/// How often we check whether to stop accepting connections
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most connections we serve at once, further clients wait until one closes
+const MAX_CONNECTIONS: usize = 256;
+
+/// Most subscriptions a single connection can keep open
+const MAX_SUBSCRIPTIONS: usize = 20;
+
+/// Most stored events we answer a single filter with
+const MAX_LIMIT: usize = 500;
+
+/// Most index entries we look at to answer a single filter
+const MAX_SCANNED: usize = 10_000;
+
+/// Most events a single account can publish to us
+const MAX_EVENTS_PER_AUTHOR: u64 = 1_000;
+
+/// Most bytes of events all accounts together can publish to us, since
+/// accounts cost nothing to open
+const MAX_PUBLISHED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// A NIP-01 relay serving the federation's signed notes and the events its
+/// accounts published to us
+///
+/// Events are kept in the module database next to their indexes, only our
+/// guardian writes them, never as part of consensus.
+#[derive(Debug, Clone)]
+pub struct EmbeddedRelay {
+    pub db: Database,
+    /// Events signed or published while clients are connected, sent to the
+    /// subscriptions they match
+    pub events: broadcast::Sender<Event>,
+    /// Serializes our writes, concurrent ones to the same counts would conflict
+    writes: Arc<Mutex<()>>,
+    /// The key printing money in development federations
+    issuer_key: Option<XOnlyPublicKey>,
+}
+
+impl EmbeddedRelay {
+    pub fn new(db: Database, issuer_key: Option<XOnlyPublicKey>) -> Self {
+        EmbeddedRelay {
+            db,
+            issuer_key,
+            events: broadcast::channel(LIVE_EVENTS_CAPACITY).0,
+            writes: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Accepts connections until the task group shuts down, which also
+    /// closes the open ones
+    pub async fn run(self, listener: TcpListener, handle: TaskHandle) {
+        info!(addr = ?listener.local_addr(), "Starting embedded relay");
+        let mut connections = JoinSet::new();
+        while !handle.is_shutting_down() {
+            let stream = tokio::select! {
+                accepted = listener.accept(), if connections.len() < MAX_CONNECTIONS => {
+                    match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            warn!("Failed to accept relay connection: {e}");
+                            continue;
+                        }
+                    }
+                }
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+                _ = sleep(SHUTDOWN_CHECK_INTERVAL) => continue,
+            };
+
+            let relay = self.clone();
+            connections.spawn(async move {
+                if let Err(e) = relay.serve(stream).await {
+                    debug!("Relay connection failed: {e}");
+                }
+            });
+        }
+        connections.shutdown().await;
+    }
+
+    /// Indexes the notes the federation signs until the task group shuts down
+    pub async fn index_signed_notes(self, handle: TaskHandle) {
+        while !handle.is_shutting_down() {
+            let next = self.index_new_signed_notes().await;
+            tokio::select! {
+                _ = self.db.wait_key_exists(&NostimintSignedNoteKey(next)) => {}
+                _ = sleep(SHUTDOWN_CHECK_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Indexes the notes signed since we last looked and sends them to open
+    /// subscriptions, returning the position the next signed note will get
+    async fn index_new_signed_notes(&self) -> u64 {
+        let _writes = self.writes.lock().await;
+        let mut dbtx = self.db.begin_transaction().await;
+        let mut next = dbtx.get_value(&NostimintRelayCursorKey).await.unwrap_or(0);
+        let mut notes = vec![];
+        while let Some(note) = dbtx.get_value(&NostimintSignedNoteKey(next)).await {
+            index_event(&mut dbtx, &note).await;
+            notes.push(note);
+            next += 1;
+        }
+        dbtx.insert_entry(&NostimintRelayCursorKey, &next).await;
+        dbtx.commit_tx().await;
+
+        for note in notes {
+            let _ = self.events.send(note);
+        }
+        next
+    }
+
+    /// Answers a single client until it disconnects
+    pub async fn serve(&self, stream: TcpStream) -> anyhow::Result<()> {
+        let mut socket = accept_async(stream).await?;
+        let mut live = self.events.subscribe();
+        let mut subscriptions = HashMap::new();
+
+        loop {
+            let replies = tokio::select! {
+                message = socket.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        self.handle_message(&text, &mut subscriptions).await
+                    }
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                },
+                event = live.recv() => match event {
+                    Ok(event) => live_replies(&subscriptions, &event),
+                    // Clients can still get the missed events with a new subscription
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!(missed, "Relay connection missed live events");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+
+            for reply in replies {
+                socket.send(Message::Text(reply.as_json())).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_message(
+        &self,
+        text: &str,
+        subscriptions: &mut HashMap<SubscriptionId, Vec<EventFilter>>,
+    ) -> Vec<RelayMessage> {
+        match ClientMessage::from_json(text) {
+            Ok(ClientMessage::Req {
+                subscription_id,
+                filters,
+            }) => {
+                if !subscriptions.contains_key(&subscription_id)
+                    && subscriptions.len() >= MAX_SUBSCRIPTIONS
+                {
+                    return vec![RelayMessage::new_notice(format!(
+                        "Only {MAX_SUBSCRIPTIONS} subscriptions per connection"
+                    ))];
+                }
+                let filters = match filters
+                    .iter()
+                    .map(EventFilter::from_filter)
+                    .collect::<anyhow::Result<Vec<_>>>()
+                {
+                    Ok(filters) => filters,
+                    Err(e) => {
+                        return vec![RelayMessage::new_notice(format!("Invalid filter: {e}"))]
+                    }
+                };
+
+                // Stored events first, then EOSE, after which only live events follow
+                let mut replies: Vec<_> = self
+                    .stored_events(&filters)
+                    .await
+                    .into_iter()
+                    .map(|event| RelayMessage::new_event(subscription_id.clone(), event.event))
+                    .collect();
+                replies.push(RelayMessage::new_eose(subscription_id.clone()));
+                subscriptions.insert(subscription_id, filters);
+                replies
+            }
+            Ok(ClientMessage::Close(subscription_id)) => {
+                subscriptions.remove(&subscription_id);
+                vec![]
+            }
+            Ok(ClientMessage::Event(event)) => {
+                let event = Event { event: *event };
+                let id = event.event.id;
+                let (accepted, message) = match self.accept_event(event).await {
+                    Ok(message) => (true, message),
+                    Err(e) => (false, e.to_string()),
+                };
+                vec![RelayMessage::new_ok(id, accepted, message)]
+            }
+            Ok(_) => vec![RelayMessage::new_notice("Unsupported message")],
+            Err(e) => vec![RelayMessage::new_notice(format!("Invalid message: {e}"))],
+        }
+    }
+
+    /// Stores an event published by one of the federation's accounts,
+    /// returning the message of our `OK` answer
+    async fn accept_event(&self, event: Event) -> anyhow::Result<String> {
+        event.event.verify().map_err(|e| anyhow!("invalid: {e}"))?;
+        ensure!(
+            event.event.as_json().len() <= MAX_EVENT_SIZE,
+            "invalid: event is larger than {MAX_EVENT_SIZE} bytes"
+        );
+        let author = XOnlyPublicKey::from_slice(&event.event.pubkey.serialize())?;
+        let size = event.event.as_json().len() as u64;
+
+        let _writes = self.writes.lock().await;
+        let mut dbtx = self.db.begin_transaction().await;
+        // The issuer's funds count the money it printed, it isn't a user's account
+        let is_account = Some(author) != self.issuer_key
+            && dbtx.get_value(&NostimintFundsKeyV1(author)).await.is_some();
+        let duplicate = dbtx
+            .get_value(&NostimintRelayEventKey(NoteId(event.event.id)))
+            .await
+            .is_some();
+        let count_key = NostimintRelayAuthorCountKey(author);
+        let count = dbtx.get_value(&count_key).await.unwrap_or(0);
+        let bytes = dbtx.get_value(&NostimintRelayBytesKey).await.unwrap_or(0);
+        let stored = is_account
+            && !duplicate
+            && count < MAX_EVENTS_PER_AUTHOR
+            && bytes + size <= MAX_PUBLISHED_BYTES;
+        if stored {
+            index_event(&mut dbtx, &event).await;
+            dbtx.insert_entry(&count_key, &(count + 1)).await;
+            dbtx.insert_entry(&NostimintRelayBytesKey, &(bytes + size))
+                .await;
+        }
+        dbtx.commit_tx().await;
+
+        ensure!(
+            is_account,
+            "blocked: only accounts of the federation can publish"
+        );
+        if duplicate {
+            return Ok("duplicate: already have this event".to_string());
+        }
+        ensure!(
+            count < MAX_EVENTS_PER_AUTHOR,
+            "rate-limited: accounts can publish at most {MAX_EVENTS_PER_AUTHOR} events"
+        );
+        ensure!(
+            stored,
+            "rate-limited: the relay is out of space for published events"
+        );
+        let _ = self.events.send(event);
+        Ok(String::new())
+    }
+
+    /// Stored events matching the filters, newest first for each filter
+    async fn stored_events(&self, filters: &[EventFilter]) -> Vec<Event> {
+        let mut seen = HashSet::new();
+        let mut matching = vec![];
+        for filter in filters {
+            for event in self.matching_events(filter).await {
+                if seen.insert(event.event.id) {
+                    matching.push(event);
+                }
+            }
+        }
+        matching
+    }
+
+    /// Stored events matching a filter, newest first
+    ///
+    /// Scans the narrowest indexes the filter allows and stops once it found
+    /// as many events as the filter's limit or looked at `MAX_SCANNED` entries.
+    async fn matching_events(&self, filter: &EventFilter) -> Vec<Event> {
+        let limit = filter.limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT);
+        let mut index_tx = self.db.begin_transaction().await;
+        let mut events_tx = self.db.begin_transaction().await;
+        let mut scanned = 0;
+        let mut events = vec![];
+
+        // Every index is newest first, so the newest events overall are among
+        // the first `limit` matches of each
+        for index in filter.indexes() {
+            let mut ids: BoxStream<'_, NoteId> = match index {
+                FilterIndex::Id(id) => stream::iter([id]).boxed(),
+                FilterIndex::Author(author) => index_tx
+                    .find_by_prefix(&NostimintRelayAuthorEventsPrefix(author))
+                    .await
+                    .map(|(NostimintRelayAuthorKey(_, _, id), ())| id)
+                    .boxed(),
+                FilterIndex::Kind(kind) => index_tx
+                    .find_by_prefix(&NostimintRelayKindEventsPrefix(kind))
+                    .await
+                    .map(|(NostimintRelayKindKey(_, _, id), ())| id)
+                    .boxed(),
+                FilterIndex::Time => index_tx
+                    .find_by_prefix(&NostimintRelayTimePrefix)
+                    .await
+                    .map(|(NostimintRelayTimeKey(_, id), ())| id)
+                    .boxed(),
+            };
+
+            let mut found = 0;
+            while found < limit && scanned < MAX_SCANNED {
+                let Some(id) = ids.next().await else {
+                    break;
+                };
+                scanned += 1;
+                let Some(event) = events_tx.get_value(&NostimintRelayEventKey(id)).await else {
+                    continue;
+                };
+                if filter.matches(&event.event) {
+                    events.push(event);
+                    found += 1;
+                }
+            }
+        }
+        index_tx.commit_tx().await;
+        events_tx.commit_tx().await;
+
+        events.sort_by_key(|event| Reverse(event.event.created_at.as_u64()));
+        events.truncate(limit);
+        events
+    }
+}
+
+/// Adds an event and its index entries to the events we serve, unless we have it
+async fn index_event(dbtx: &mut DatabaseTransaction<'_>, event: &Event) {
+    let id = NoteId(event.event.id);
+    if dbtx.get_value(&NostimintRelayEventKey(id)).await.is_some() {
+        return;
+    }
+    let author = XOnlyPublicKey::from_slice(&event.event.pubkey.serialize()).expect("same curve");
+    // Indexes list the newest events first
+    let age = u64::MAX - event.event.created_at.as_u64();
+
+    dbtx.insert_new_entry(&NostimintRelayEventKey(id), event)
+        .await;
+    dbtx.insert_new_entry(&NostimintRelayTimeKey(age, id), &())
+        .await;
+    dbtx.insert_new_entry(
+        &NostimintRelayKindKey(event.event.kind.as_u64(), age, id),
+        &(),
+    )
+    .await;
+    dbtx.insert_new_entry(&NostimintRelayAuthorKey(author, age, id), &())
+        .await;
+}
+
+/// An event for each subscription it matches
+fn live_replies(
+    subscriptions: &HashMap<SubscriptionId, Vec<EventFilter>>,
+    event: &Event,
+) -> Vec<RelayMessage> {
+    subscriptions
+        .iter()
+        .filter(|(_, filters)| filters.iter().any(|filter| filter.matches(&event.event)))
+        .map(|(id, _)| RelayMessage::new_event(id.clone(), event.event.clone()))
+        .collect()
+}
+
+/// A NIP-01 filter, events match if they match every condition that is set
+#[derive(Debug, Clone, Default, Deserialize)]
+struct EventFilter {
+    ids: Option<Vec<String>>,
+    authors: Option<Vec<String>>,
+    kinds: Option<Vec<u64>>,
+    since: Option<u64>,
+    until: Option<u64>,
+    limit: Option<usize>,
+    /// Tag conditions such as `#e`, other fields like `search` are ignored
+    #[serde(flatten)]
+    tags: BTreeMap<String, serde_json::Value>,
+}
+
+/// An index of the events we serve a filter is answered from
+#[derive(Debug, Clone, Copy)]
+enum FilterIndex {
+    Id(NoteId),
+    Author(XOnlyPublicKey),
+    Kind(u64),
+    Time,
+}
+
+impl EventFilter {
+    fn from_filter(filter: &Filter) -> anyhow::Result<Self> {
+        Ok(serde_json::from_value(serde_json::to_value(filter)?)?)
+    }
+
+    /// The narrowest indexes covering every event the filter matches, ids and
+    /// authors only narrow it down if given in full rather than as prefixes
+    fn indexes(&self) -> Vec<FilterIndex> {
+        let ids = self.ids.as_ref().and_then(|ids| {
+            ids.iter()
+                .map(|id| Some(FilterIndex::Id(NoteId(EventId::from_hex(id).ok()?))))
+                .collect::<Option<Vec<_>>>()
+        });
+        let authors = self.authors.as_ref().and_then(|authors| {
+            authors
+                .iter()
+                .map(|author| Some(FilterIndex::Author(XOnlyPublicKey::from_str(author).ok()?)))
+                .collect::<Option<Vec<_>>>()
+        });
+        let kinds = self
+            .kinds
+            .as_ref()
+            .map(|kinds| kinds.iter().copied().map(FilterIndex::Kind).collect());
+
+        ids.or(authors)
+            .or(kinds)
+            .unwrap_or_else(|| vec![FilterIndex::Time])
+    }
+
+    fn matches(&self, event: &nostr_sdk::Event) -> bool {
+        // Ids and authors can be given as prefixes
+        let id = event.id.to_hex();
+        let author = event.pubkey.to_string();
+        let created_at = event.created_at.as_u64();
+
+        self.ids
+            .as_ref()
+            .map_or(true, |ids| ids.iter().any(|prefix| id.starts_with(prefix)))
+            && self.authors.as_ref().map_or(true, |authors| {
+                authors.iter().any(|prefix| author.starts_with(prefix))
+            })
+            && self
+                .kinds
+                .as_ref()
+                .map_or(true, |kinds| kinds.contains(&event.kind.as_u64()))
+            && self.since.map_or(true, |since| created_at >= since)
+            && self.until.map_or(true, |until| created_at <= until)
+            && self
+                .tags
+                .iter()
+                .filter_map(|(name, values)| Some((name.strip_prefix('#')?, values.as_array()?)))
+                .all(|(name, values)| {
+                    event.tags.iter().map(|tag| tag.as_vec()).any(|tag| {
+                        tag.first().map(String::as_str) == Some(name)
+                            && tag.get(1).map_or(false, |value| {
+                                values.iter().any(|v| v.as_str() == Some(value))
+                            })
+                    })
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+    use fedimint_nostimint_common::NoteRequest;
+    use nostr_sdk::{EventBuilder, Keys, Kind};
+    use tokio_tungstenite::connect_async;
+
+    use super::*;
+    use crate::tests::memory_db;
+
+    #[tokio::test]
+    async fn serves_events_from_embedded_relay() -> anyhow::Result<()> {
+        let db = memory_db();
+        let federation = Keys::generate();
+        let user = Keys::generate();
+        let request = NoteRequest {
+            kind: 1,
+            content: "signed by the federation".to_string(),
+            tags: vec![],
+            created_at: 1_700_000_000,
+        };
+        let note = request.to_unsigned_event(federation.public_key(), request.created_at)?;
+        let signed = note.event.clone().sign(&federation)?;
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &NostimintSignedNoteKey(0),
+            &Event {
+                event: signed.clone(),
+            },
+        )
+        .await;
+        let account = secp256k1::XOnlyPublicKey::from_slice(&user.public_key().serialize())?;
+        dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::ZERO)
+            .await;
+        dbtx.commit_tx().await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        let relay = EmbeddedRelay::new(db, None);
+        assert_eq!(relay.index_new_signed_notes().await, 1);
+        assert_eq!(relay.index_new_signed_notes().await, 1);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("client connects");
+            relay.serve(stream).await.expect("relay serves client");
+        });
+        let (mut socket, _) = connect_async(url).await?;
+        let notes = SubscriptionId::new("notes");
+
+        // Stored events come before EOSE
+        send_text(&mut socket, r#"["REQ","notes",{"kinds":[1]}]"#).await?;
+        match next_relay_message(&mut socket).await? {
+            RelayMessage::Event {
+                subscription_id,
+                event,
+            } => {
+                assert_eq!(subscription_id, notes);
+                assert_eq!(event.id, signed.id);
+            }
+            message => anyhow::bail!("Expected the signed note, got {message:?}"),
+        }
+        assert_eq!(
+            next_relay_message(&mut socket).await?,
+            RelayMessage::new_eose(notes.clone())
+        );
+
+        // Accounts can publish, and open subscriptions get their events live
+        let published = EventBuilder::new_text_note("hello relay", &[]).to_event(&user)?;
+        let message = ClientMessage::new_event(published.clone()).as_json();
+        send_text(&mut socket, &message).await?;
+        assert_eq!(
+            next_relay_message(&mut socket).await?,
+            RelayMessage::new_ok(published.id, true, "")
+        );
+        match next_relay_message(&mut socket).await? {
+            RelayMessage::Event { event, .. } => assert_eq!(event.id, published.id),
+            message => anyhow::bail!("Expected the live event, got {message:?}"),
+        }
+
+        let stranger = EventBuilder::new_text_note("spam", &[]).to_event(&Keys::generate())?;
+        let message = ClientMessage::new_event(stranger.clone()).as_json();
+        send_text(&mut socket, &message).await?;
+        match next_relay_message(&mut socket).await? {
+            RelayMessage::Ok {
+                event_id, status, ..
+            } => {
+                assert_eq!(event_id, stranger.id);
+                assert!(!status);
+            }
+            message => anyhow::bail!("Expected the event to be rejected, got {message:?}"),
+        }
+
+        // Closed subscriptions get no more events
+        send_text(&mut socket, r#"["CLOSE","notes"]"#).await?;
+        let later = EventBuilder::new_text_note("after close", &[]).to_event(&user)?;
+        let message = ClientMessage::new_event(later.clone()).as_json();
+        send_text(&mut socket, &message).await?;
+        assert_eq!(
+            next_relay_message(&mut socket).await?,
+            RelayMessage::new_ok(later.id, true, "")
+        );
+        let filter = format!(r#"["REQ","later",{{"ids":["{}"]}}]"#, later.id.to_hex());
+        send_text(&mut socket, &filter).await?;
+        match next_relay_message(&mut socket).await? {
+            RelayMessage::Event {
+                subscription_id,
+                event,
+            } => {
+                assert_eq!(subscription_id, SubscriptionId::new("later"));
+                assert_eq!(event.id, later.id);
+            }
+            message => anyhow::bail!("Expected the stored event, got {message:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_filters_from_indexes() -> anyhow::Result<()> {
+        let db = memory_db();
+        let user = Keys::generate();
+        let account = XOnlyPublicKey::from_slice(&user.public_key().serialize())?;
+        let issuer = Keys::generate();
+        let issuer_key = XOnlyPublicKey::from_slice(&issuer.public_key().serialize())?;
+        let mut dbtx = db.begin_transaction().await;
+        for account in [account, issuer_key] {
+            dbtx.insert_new_entry(&NostimintFundsKeyV1(account), &Amount::ZERO)
+                .await;
+        }
+        dbtx.commit_tx().await;
+        let relay = EmbeddedRelay::new(db.clone(), Some(issuer_key));
+
+        for content in ["first", "second", "third"] {
+            let event = EventBuilder::new_text_note(content, &[]).to_event(&user)?;
+            relay.accept_event(Event { event }).await?;
+        }
+        let reaction = EventBuilder::new(Kind::Reaction, "+", &[]).to_event(&user)?;
+        relay
+            .accept_event(Event {
+                event: reaction.clone(),
+            })
+            .await?;
+
+        let notes = EventFilter {
+            kinds: Some(vec![1]),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(relay.stored_events(&[notes]).await.len(), 2);
+        let by_author = EventFilter {
+            authors: Some(vec![user.public_key().to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(relay.stored_events(&[by_author]).await.len(), 4);
+        let by_id = EventFilter {
+            ids: Some(vec![reaction.id.to_hex()]),
+            ..Default::default()
+        };
+        let events = relay.stored_events(&[by_id]).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event.id, reaction.id);
+
+        // Prefixes can't use the author index, so the kind index answers
+        let by_prefix = EventFilter {
+            authors: Some(vec![user.public_key().to_string()[..8].to_string()]),
+            kinds: Some(vec![7]),
+            ..Default::default()
+        };
+        assert_eq!(relay.stored_events(&[by_prefix]).await.len(), 1);
+
+        // The issuer can't publish even though it has funds
+        let printed = EventBuilder::new_text_note("free money", &[]).to_event(&issuer)?;
+        assert!(relay.accept_event(Event { event: printed }).await.is_err());
+
+        // Published events outlive the relay, the same way a restart would
+        let restarted = EmbeddedRelay::new(db.clone(), Some(issuer_key));
+        let everything = EventFilter::default();
+        assert_eq!(restarted.stored_events(&[everything]).await.len(), 4);
+        let mut dbtx = db.begin_transaction().await;
+        let published = dbtx.get_value(&NostimintRelayBytesKey).await.unwrap_or(0);
+        dbtx.commit_tx().await;
+        assert!(published > 0);
+
+        // Once the relay is out of space it refuses events of every account
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&NostimintRelayBytesKey, &MAX_PUBLISHED_BYTES)
+            .await;
+        dbtx.commit_tx().await;
+        let late = EventBuilder::new_text_note("fourth", &[]).to_event(&user)?;
+        assert!(relay.accept_event(Event { event: late }).await.is_err());
+
+        Ok(())
+    }
+
+    /// Sends a raw NIP-01 message to a relay
+    async fn send_text<S>(socket: &mut S, text: &str) -> anyhow::Result<()>
+    where
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::error::Error + Send + Sync + 'static,
+    {
+        socket.send(Message::Text(text.to_string())).await?;
+        Ok(())
+    }
+
+    /// Waits for the next message a relay sends us
+    async fn next_relay_message<S>(socket: &mut S) -> anyhow::Result<RelayMessage>
+    where
+        S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        while let Some(message) = socket.next().await {
+            if let Message::Text(text) = message? {
+                return Ok(RelayMessage::from_json(&text)?);
+            }
+        }
+        anyhow::bail!("Relay closed the connection")
+    }
+}
@@ -1,15 +1,42 @@
 use fedimintd::fedimintd::Fedimintd;
 
+/// Comma-separated relay URLs this guardian publishes signed notes to
+const FM_NOSTIMINT_RELAYS_ENV: &str = "FM_NOSTIMINT_RELAYS";
+
+/// JSON of the notes this guardian vetoes, see `NotePolicyConfig`
+const FM_NOSTIMINT_POLICY_ENV: &str = "FM_NOSTIMINT_POLICY";
+
+/// Address this guardian serves its embedded nostr relay on, e.g. `0.0.0.0:4848`
+const FM_NOSTIMINT_RELAY_BIND_ENV: &str = "FM_NOSTIMINT_RELAY_BIND";
+
+/// Hex public key that can print money, only for development federations
+const FM_NOSTIMINT_ISSUER_KEY_ENV: &str = "FM_NOSTIMINT_ISSUER_KEY";
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let mut params = fedimint_nostimint_server::NostimintGenParams::default();
+    if let Ok(relays) = std::env::var(FM_NOSTIMINT_RELAYS_ENV) {
+        params.local.relays = relays
+            .split(',')
+            .map(str::trim)
+            .filter(|relay| !relay.is_empty())
+            .map(ToString::to_string)
+            .collect();
+    }
+    if let Ok(policy) = std::env::var(FM_NOSTIMINT_POLICY_ENV) {
+        params.local.policy = serde_json::from_str(&policy)?;
+    }
+    if let Ok(relay_bind) = std::env::var(FM_NOSTIMINT_RELAY_BIND_ENV) {
+        params.local.relay_bind = Some(relay_bind);
+    }
+    if let Ok(issuer_key) = std::env::var(FM_NOSTIMINT_ISSUER_KEY_ENV) {
+        params.consensus.issuer_key = Some(issuer_key.parse()?);
+    }
+
     Fedimintd::new()?
         .with_default_modules()
-        .with_module(fedimint_nostimint_server::NostimintGen)
-        .with_extra_module_inits_params(
-            3,
-            fedimint_nostimint_server::KIND,
-            fedimint_nostimint_server::NostimintGenParams::default(),
-        )
+        .with_module(fedimint_nostimint_server::NostimintGen::default())
+        .with_extra_module_inits_params(3, fedimint_nostimint_server::KIND, params)
         .run()
         .await
 }
@@ -4,6 +4,13 @@ use std::{env, fmt::Write, path::Path};
 use tokio::fs;
 use tracing::{debug, info};
 
+/// Public key the test federation prints money with
+const DEV_ISSUER_KEY: &str = "1b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f";
+
+/// Secret of [`DEV_ISSUER_KEY`], it is public so only the test federation
+/// opts in to printing money
+const DEV_ISSUER_SECRET: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
 #[tokio::test(flavor = "multi_thread")]
 async fn starter_test() -> anyhow::Result<()> {
     let (process_mgr, _) = setup().await?;
@@ -13,6 +20,21 @@ async fn starter_test() -> anyhow::Result<()> {
     let init_bal = fed.client_balance().await?;
     assert_eq!(init_bal, 0);
 
+    // Other keys can't print money
+    env::set_var("FM_NOSTIMINT_ISSUER_SECRET", "02".repeat(32));
+    let printed = cmd!(
+        "fedimint-cli",
+        "module",
+        "--module=3",
+        "print-money",
+        "1500"
+    )
+    .run()
+    .await;
+    assert!(printed.is_err());
+    env::set_var("FM_NOSTIMINT_ISSUER_SECRET", DEV_ISSUER_SECRET);
+    assert_eq!(fed.client_balance().await?, 0);
+
     cmd!(
         "fedimint-cli",
         "module",
@@ -54,6 +76,14 @@ async fn setup() -> anyhow::Result<(ProcessManager, TaskGroup)> {
         writeln!(env_string, r#"export {var}="{value}""#)?; // hope that value doesn't contain a "
         std::env::set_var(var, value);
     }
+    // Only the test federation opts in to printing money
+    for (var, value) in [
+        ("FM_NOSTIMINT_ISSUER_KEY", DEV_ISSUER_KEY),
+        ("FM_NOSTIMINT_ISSUER_SECRET", DEV_ISSUER_SECRET),
+    ] {
+        writeln!(env_string, r#"export {var}="{value}""#)?;
+        std::env::set_var(var, value);
+    }
     write_overwrite_async(globals.FM_TEST_DIR.join("env"), env_string).await?;
     info!("Test setup in {:?}", globals.FM_DATA_DIR);
     let process_mgr = ProcessManager::new(globals);